@@ -0,0 +1,260 @@
+use std::{cmp::Ordering, collections::HashSet};
+
+use serde::Serialize;
+
+use crate::{
+    value::{ColumnType, Row, Value},
+    QueryError,
+};
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize)]
+pub enum AggregateFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunc {
+    pub fn from_name(name: &str) -> Option<AggregateFunc> {
+        match name {
+            "count" => Some(AggregateFunc::Count),
+            "sum" => Some(AggregateFunc::Sum),
+            "avg" => Some(AggregateFunc::Avg),
+            "min" => Some(AggregateFunc::Min),
+            "max" => Some(AggregateFunc::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AggregateFunc::Count => "count",
+            AggregateFunc::Sum => "sum",
+            AggregateFunc::Avg => "avg",
+            AggregateFunc::Min => "min",
+            AggregateFunc::Max => "max",
+        }
+    }
+}
+
+/// An aggregate call over one column of the dataset, `col` is only None for `count(*)`
+///
+/// A distinct aggregate only sees the first occurrence of each value in its group
+#[derive(Debug, PartialEq, Clone)]
+pub struct Aggregate {
+    pub func: AggregateFunc,
+    pub distinct: bool,
+    pub col: Option<usize>,
+}
+
+impl Aggregate {
+    /// The type of the aggregated values, given the type of the column being aggregated
+    pub fn result_type(&self, input: Option<ColumnType>) -> ColumnType {
+        match self.func {
+            AggregateFunc::Count => ColumnType::Int,
+            AggregateFunc::Avg => ColumnType::Float,
+            AggregateFunc::Sum | AggregateFunc::Min | AggregateFunc::Max => {
+                input.unwrap_or(ColumnType::Int)
+            }
+        }
+    }
+
+    pub fn accumulator(&self) -> Accumulator {
+        let state = match self.func {
+            AggregateFunc::Count => AccumulatorState::Count(0),
+            AggregateFunc::Sum => AccumulatorState::Sum(None),
+            AggregateFunc::Avg => AccumulatorState::Avg(0.0, 0),
+            AggregateFunc::Min => AccumulatorState::Min(None),
+            AggregateFunc::Max => AccumulatorState::Max(None),
+        };
+        Accumulator {
+            col: self.col,
+            seen: if self.distinct {
+                Some(HashSet::new())
+            } else {
+                None
+            },
+            state,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum AccumulatorState {
+    Count(i64),
+    Sum(Option<Value>),
+    Avg(f64, usize),
+    Min(Option<Value>),
+    Max(Option<Value>),
+}
+
+/// Running state of one aggregate for one group
+#[derive(Debug)]
+pub struct Accumulator {
+    col: Option<usize>,
+    /// Values already aggregated, only kept for distinct aggregates
+    seen: Option<HashSet<Value>>,
+    state: AccumulatorState,
+}
+
+impl Accumulator {
+    pub fn update(&mut self, x: &Row) -> Result<(), QueryError> {
+        let val = match self.col {
+            // NULLs are skipped by every aggregate
+            Some(col) if x[col].is_null() => return Ok(()),
+            Some(col) => &x[col],
+            // count(*), every row counts
+            None => &Value::Null,
+        };
+        if let Some(seen) = &mut self.seen {
+            if !seen.insert(val.clone()) {
+                return Ok(());
+            }
+        }
+        match &mut self.state {
+            AccumulatorState::Count(count) => *count += 1,
+            AccumulatorState::Sum(sum) => {
+                if let Some(num) = val.expect_number()? {
+                    *sum = match sum {
+                        Some(s) => s.add(num),
+                        None => Some(num.clone()),
+                    };
+                }
+            }
+            AccumulatorState::Avg(sum, count) => {
+                if let Some(num) = val.expect_number()? {
+                    *sum += num.as_f64().unwrap_or_default();
+                    *count += 1;
+                }
+            }
+            AccumulatorState::Min(min) => {
+                if min
+                    .as_ref()
+                    .is_none_or(|m| val.sort_cmp(m) == Ordering::Less)
+                {
+                    *min = Some(val.clone());
+                }
+            }
+            AccumulatorState::Max(max) => {
+                if max
+                    .as_ref()
+                    .is_none_or(|m| val.sort_cmp(m) == Ordering::Greater)
+                {
+                    *max = Some(val.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The final value of the aggregate, NULL if the group had nothing to aggregate over
+    pub fn finish(&self) -> Value {
+        match &self.state {
+            AccumulatorState::Count(count) => Value::Int(*count),
+            AccumulatorState::Avg(_, 0) => Value::Null,
+            AccumulatorState::Avg(sum, count) => Value::Float(sum / *count as f64),
+            AccumulatorState::Sum(val)
+            | AccumulatorState::Min(val)
+            | AccumulatorState::Max(val) => val.clone().unwrap_or(Value::Null),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the aggregate over a group of one column rows. `col` is None for `count(*)`
+    fn aggregate(
+        func: AggregateFunc,
+        distinct: bool,
+        col: Option<usize>,
+        group: &[Value],
+    ) -> Value {
+        let mut acc = Aggregate {
+            func,
+            distinct,
+            col,
+        }
+        .accumulator();
+        for val in group {
+            acc.update(&vec![val.clone()]).unwrap();
+        }
+        acc.finish()
+    }
+
+    /// Compares by Debug, so an int result doesn't pass for an equal float
+    fn assert_aggregates_to(func: AggregateFunc, distinct: bool, group: &[Value], expected: Value) {
+        let res = aggregate(func, distinct, Some(0), group);
+        assert_eq!(
+            format!("{:?}", res),
+            format!("{:?}", expected),
+            "{}(distinct: {}) over {:?}",
+            func.name(),
+            distinct,
+            group
+        );
+    }
+
+    #[test]
+    fn aggregates_skip_nulls() {
+        use AggregateFunc::*;
+        let group = [Value::Int(3), Value::Null, Value::Int(3), Value::Int(5)];
+        let cases = [
+            (Count, Value::Int(3)),
+            (Sum, Value::Int(11)),
+            (Avg, Value::Float(11.0 / 3.0)),
+            (Min, Value::Int(3)),
+            (Max, Value::Int(5)),
+        ];
+        for (func, expected) in cases {
+            assert_aggregates_to(func, false, &group, expected);
+        }
+    }
+
+    #[test]
+    fn count_star_counts_every_row() {
+        let group = [Value::Null, Value::Int(1), Value::Null];
+        assert_eq!(
+            aggregate(AggregateFunc::Count, false, None, &group),
+            Value::Int(3)
+        );
+        assert_eq!(
+            aggregate(AggregateFunc::Count, false, Some(0), &group),
+            Value::Int(1)
+        );
+    }
+
+    #[test]
+    fn all_null_groups_aggregate_to_null_except_count() {
+        use AggregateFunc::*;
+        let group = [Value::Null, Value::Null];
+        for func in [Sum, Avg, Min, Max] {
+            assert_aggregates_to(func, false, &group, Value::Null);
+        }
+        assert_aggregates_to(Count, false, &group, Value::Int(0));
+    }
+
+    #[test]
+    fn distinct_aggregates_ignore_repeats() {
+        use AggregateFunc::*;
+        // 3 and 3.0 are the same value, so only the first is aggregated
+        let group = [
+            Value::Int(3),
+            Value::Int(5),
+            Value::Float(3.0),
+            Value::Null,
+            Value::Int(5),
+        ];
+        let cases = [
+            (Count, Value::Int(2)),
+            (Sum, Value::Int(8)),
+            (Avg, Value::Float(4.0)),
+        ];
+        for (func, expected) in cases {
+            assert_aggregates_to(func, true, &group, expected);
+        }
+    }
+}
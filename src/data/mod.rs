@@ -1,116 +1,276 @@
 use csv::StringRecord;
-use std::{collections::HashMap, fs::File, io::Write};
+use std::{collections::HashMap, time::UNIX_EPOCH};
 
-use crate::QueryError;
+use crate::{
+    value::{ColumnType, Row, Value},
+    QueryError,
+};
 
 // fixme: eventually the cli params will be passed into here somehow
 const CONFIG_FILE_PATH: &str = "./data/config.csv";
 const CONFIG_FILE_NAME: &str = "config.csv";
 const DATA_DIR_PATH: &str = "./data/";
+const CONFIG_HEADERS: [&str; 4] = ["dataset", "column", "type", "modified"];
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub kind: ColumnType,
+}
+
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub name: String,
+    pub columns: Vec<Column>,
+}
+
+/// The available datasets and their schemas, which queries are resolved against
+pub trait Catalog {
+    fn get(&self, dataset: &str) -> Option<&Schema>;
+
+    /// The names of every dataset, used to suggest one when a query names a dataset that doesn't exist
+    fn datasets(&self) -> Vec<&str>;
+}
+
+/// A catalog of schemas held in memory. The repl reads the config file into one of these,
+/// tests and editors can build one directly so queries can be checked without the data being present
+#[derive(Debug, Default)]
+pub struct MemoryCatalog {
+    schemas: Vec<Schema>,
+}
+
+impl MemoryCatalog {
+    pub fn new() -> MemoryCatalog {
+        MemoryCatalog::default()
+    }
+
+    /// Adds the schema, replacing any existing dataset with the same name
+    pub fn add(&mut self, schema: Schema) {
+        self.schemas.retain(|x| x.name != schema.name);
+        self.schemas.push(schema);
+    }
+}
+
+impl Catalog for MemoryCatalog {
+    fn get(&self, dataset: &str) -> Option<&Schema> {
+        self.schemas.iter().find(|schema| schema.name == dataset)
+    }
+
+    fn datasets(&self) -> Vec<&str> {
+        self.schemas
+            .iter()
+            .map(|schema| schema.name.as_str())
+            .collect()
+    }
+}
+
+/// The rows of a dataset, parsed into values of its columns' types
+pub struct Dataset {
+    pub rows: Vec<Row>,
+    /// The text of the cells whose value prints differently from how the file wrote it, like `007` or `65.50`,
+    /// by row and column. Results show the file's text, so values pass through queries unchanged
+    originals: HashMap<(usize, usize), String>,
+}
+
+impl Dataset {
+    /// The cell as the file wrote it
+    pub fn text(&self, row: usize, col: usize) -> String {
+        match self.originals.get(&(row, col)) {
+            Some(text) => text.clone(),
+            None => self.rows[row][col].to_string(),
+        }
+    }
+}
 
 pub struct DataAccessor {
-    pub ready: Vec<String>,
-    loaded: HashMap<String, Vec<StringRecord>>,
+    catalog: MemoryCatalog,
+    loaded: HashMap<String, Dataset>,
 }
 
 impl DataAccessor {
-    /// This is stupid, but I don't have enough braincells to put this into the config right now
-    ///
-    /// Seems like the smart thing to do would be to make the config a json and have it keep track of the available datasets and their headers
-    /// This will do for now so I can throw it into the Parser, this api should be fine
-    pub fn get_headers(dataset: &String) -> Result<Vec<String>, QueryError> {
-        let path = DATA_DIR_PATH.to_string() + dataset.as_ref() + ".csv";
-        let mut reader = csv::Reader::from_path(path)?;
-        let headers = reader
-            .headers()?
-            .into_iter()
-            .map(|s| s.to_string())
-            .collect();
-        Ok(headers)
+    pub fn catalog(&self) -> &MemoryCatalog {
+        &self.catalog
     }
 
-    pub fn get(&mut self, dataset: String) -> Result<&Vec<StringRecord>, QueryError> {
+    pub fn get(&mut self, dataset: String) -> Result<&Dataset, QueryError> {
         if !self.loaded.contains_key(&dataset) {
             self.load(dataset.clone())?;
         }
-        Ok(&self.loaded.get(&dataset).unwrap())
+        Ok(self.loaded.get(&dataset).unwrap())
     }
 
     fn load(&mut self, dataset: String) -> Result<(), QueryError> {
-        if !self.ready.contains(&dataset) {
-            Err(QueryError::BadSyntax("Requested dataset does not exist"))
-        } else {
-            let path = DATA_DIR_PATH.to_string() + dataset.as_ref() + ".csv";
-            let mut reader = csv::Reader::from_path(path)?;
-            let loaded: Result<Vec<StringRecord>, QueryError> = reader
-                .records()
-                // Convert csv errors to QueryErrors
-                .map(|r| r.map_err(|e| QueryError::from(e)))
-                .collect();
-            self.loaded.insert(dataset, loaded?);
-            Ok(())
+        let schema = self
+            .catalog
+            .get(&dataset)
+            .ok_or(QueryError::QueryFailed("Requested dataset does not exist"))?;
+        let path = DATA_DIR_PATH.to_string() + dataset.as_ref() + ".csv";
+        let mut reader = csv::Reader::from_path(path)?;
+        // Values are parsed here once, so nothing after loading has to look at raw strings
+        let mut rows = Vec::new();
+        let mut originals = HashMap::new();
+        for rec in reader.records() {
+            let rec = rec?;
+            let row = DataAccessor::parse_record(&rec, schema)?;
+            for (col, (field, val)) in rec.iter().zip(row.iter()).enumerate() {
+                if val.to_string() != field {
+                    originals.insert((rows.len(), col), field.to_string());
+                }
+            }
+            rows.push(row);
         }
+        self.loaded.insert(dataset, Dataset { rows, originals });
+        Ok(())
     }
 
-    /// Attempt to read the available datasets from the config.csv file
-    /// Errors if the config file doesnt exist or is invalid csv. In both of those cases, we should recreate the config file
-    fn read_config() -> Result<Vec<String>, QueryError> {
-        let mut config = csv::Reader::from_path(CONFIG_FILE_PATH)?;
-        // for x in config.records() {
-        //     println!("{:?}", x);
-        // }
-        config
-            .records()
-            .map(|str_record_res| match str_record_res {
-                Ok(str_record) => Ok(str_record[0].to_string()),
-                Err(e) => Err(QueryError::from(e)),
+    fn parse_record(rec: &StringRecord, schema: &Schema) -> Result<Row, QueryError> {
+        rec.iter()
+            .zip(schema.columns.iter())
+            .map(|(field, col)| {
+                col.kind.parse(field).ok_or_else(|| {
+                    QueryError::BadCSV(format!(
+                        "'{}' in column '{}' of dataset '{}' is not a {}, the catalog may be out of date",
+                        field,
+                        col.name,
+                        schema.name,
+                        col.kind.name()
+                    ))
+                })
             })
             .collect()
     }
 
+    /// Infer the type of each column from every row of the dataset
+    ///
+    /// A column takes the narrowest type that fits all of its values, so loading the dataset never finds a value that doesn't fit.
+    /// Columns that are entirely empty are strings
+    fn infer_schema(name: &str) -> Result<Schema, QueryError> {
+        let path = DATA_DIR_PATH.to_string() + name + ".csv";
+        let mut reader = csv::Reader::from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let mut kinds: Vec<Option<ColumnType>> = vec![None; headers.len()];
+        for rec in reader.records() {
+            for (kind, field) in kinds.iter_mut().zip(rec?.iter()) {
+                if let Some(field_kind) = ColumnType::of(&Value::parse(field)) {
+                    *kind = Some(kind.map_or(field_kind, |k| k.unify(field_kind)));
+                }
+            }
+        }
+        let columns = headers
+            .iter()
+            .zip(kinds)
+            .map(|(name, kind)| Column {
+                name: name.to_string(),
+                kind: kind.unwrap_or(ColumnType::String),
+            })
+            .collect();
+        Ok(Schema {
+            name: name.to_string(),
+            columns,
+        })
+    }
+
+    /// When the dataset's file was last modified, as recorded in the config to tell if its inferred types are still valid
+    fn modified(name: &str) -> Result<String, QueryError> {
+        let path = DATA_DIR_PATH.to_string() + name + ".csv";
+        let modified = std::fs::metadata(path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| QueryError::FileError(e.to_string()))?;
+        Ok(modified.as_nanos().to_string())
+    }
+
+    /// Attempt to read the catalog from the config.csv file, one `dataset,column,type,modified` row per column
+    /// Errors if the config file doesnt exist, is invalid or a dataset has changed since its types were inferred.
+    /// In all of those cases, we should recreate the config file
+    fn read_config() -> Result<MemoryCatalog, QueryError> {
+        let mut config = csv::Reader::from_path(CONFIG_FILE_PATH)?;
+        if config.headers()? != CONFIG_HEADERS.as_ref() {
+            return Err(QueryError::BadCSV(String::from(
+                "Config file is from an older version",
+            )));
+        }
+        let mut schemas: Vec<Schema> = Vec::new();
+        for rec in config.records() {
+            let rec = rec?;
+            if schemas.iter().all(|schema| schema.name != rec[0])
+                && DataAccessor::modified(&rec[0])? != rec[3]
+            {
+                return Err(QueryError::BadCSV(format!(
+                    "Dataset '{}' has changed since its column types were inferred",
+                    &rec[0]
+                )));
+            }
+            let kind = ColumnType::from_name(&rec[2])
+                .ok_or_else(|| QueryError::BadCSV(format!("Unknown column type '{}'", &rec[2])))?;
+            let col = Column {
+                name: rec[1].to_string(),
+                kind,
+            };
+            match schemas.iter_mut().find(|schema| schema.name == rec[0]) {
+                Some(schema) => schema.columns.push(col),
+                None => schemas.push(Schema {
+                    name: rec[0].to_string(),
+                    columns: vec![col],
+                }),
+            }
+        }
+        Ok(MemoryCatalog { schemas })
+    }
+
     /// Called when the config file doesn't exist/can't be accessed anymore: so create a new config file
-    /// Returns the list of dataset filenames loaded from the data dir
-    fn recreate_config() -> Result<Vec<String>, QueryError> {
+    /// Returns the catalog of datasets loaded from the data dir
+    fn recreate_config() -> Result<MemoryCatalog, QueryError> {
         println!(">> Recreating config");
-        let mut config_file = File::create(CONFIG_FILE_PATH)?;
-        let mut datasets = Vec::new();
-        config_file.write_all(b"dataset\n")?;
-
-        for dataset_name in std::fs::read_dir(DATA_DIR_PATH)? {
-            let name = dataset_name?.file_name().into_string();
-            match name {
-                Ok(name) => {
-                    // Filter the config from the available datasets
-                    if name.as_str() == CONFIG_FILE_NAME {
-                        continue;
-                    } else {
-                        // Remove the .csv file extension
-                        let cleaned = name.split(".csv").collect::<Vec<&str>>()[0];
-                        config_file.write_all(cleaned.as_bytes())?;
-                        config_file.write_all("\n".as_bytes())?;
-                        datasets.push(cleaned.to_string());
-                    }
-                }
-                Err(_) => {
-                    return Err(QueryError::FileError(String::from(
-                        "Invalid unicode data in filename, unable to use as dataset name",
-                    )));
+        let mut config = csv::Writer::from_path(CONFIG_FILE_PATH)?;
+        let mut schemas = Vec::new();
+        config.write_record(CONFIG_HEADERS)?;
+
+        for entry in std::fs::read_dir(DATA_DIR_PATH)? {
+            let path = entry?.path();
+            // Only csv files are datasets, anything else in the data dir is ignored
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) if name != CONFIG_FILE_NAME && path.is_file() => name,
+                _ => continue,
+            };
+            let dataset = match name.strip_suffix(".csv") {
+                Some(dataset) => dataset,
+                None => continue,
+            };
+            // Read before inferring, so a change made while inferring is caught next time
+            let inferred = DataAccessor::modified(dataset).and_then(|modified| {
+                DataAccessor::infer_schema(dataset).map(|schema| (modified, schema))
+            });
+            // A file that can't be read is left out, so it doesn't stop queries on the other datasets
+            let (modified, schema) = match inferred {
+                Ok(inferred) => inferred,
+                Err(e) => {
+                    println!(
+                        ">> Skipping '{}', it could not be read as a dataset: {}",
+                        name, e
+                    );
+                    continue;
                 }
+            };
+            for col in schema.columns.iter() {
+                config.write_record([dataset, &col.name, col.kind.name(), &modified])?;
             }
+            schemas.push(schema);
         }
-        Ok(datasets)
+        config.flush()?;
+        Ok(MemoryCatalog { schemas })
     }
 
     /// Try to load config data to prepare for reading data
     ///
     /// May fail if there are invalid dataset names or the os is unable to read/write to the data dir/config file
     pub fn new() -> Result<DataAccessor, QueryError> {
-        let ready = match DataAccessor::read_config() {
-            Ok(datasets) => datasets,
+        let catalog = match DataAccessor::read_config() {
+            Ok(catalog) => catalog,
             Err(_) => DataAccessor::recreate_config()?, // Old config file was invalid for some reason, rewrite it
         };
         Ok(DataAccessor {
-            ready,
+            catalog,
             loaded: HashMap::new(),
         })
     }
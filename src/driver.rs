@@ -1,24 +1,126 @@
-use csv::StringRecord;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
 
-use crate::{data::DataAccessor, parser::ParsedQuery, QueryError};
+use crate::{
+    aggregate::{Accumulator, Aggregate},
+    data::{Catalog, DataAccessor},
+    eval::Eval,
+    filter::FilterRule,
+    parser::{OrderKey, ParsedQuery},
+    planner::Plan,
+    value::Row,
+    QueryError,
+};
+
+/// A record along with the index of the dataset row it came from. Aggregated records come from the first row of their group
+type Record<'r> = (usize, &'r Row);
 
 pub struct Driver {
     data: DataAccessor,
 }
 
 impl Driver {
-    pub fn perform_query(&mut self, query: ParsedQuery) -> Result<Vec<Vec<String>>, QueryError> {
-        let rows = self.data.get(query.from)?;
-        println!("before filter: {:?}", rows);
-        if let Some(filter) = query.filter {
-            let filtered: Result<Vec<&StringRecord>, QueryError> = rows
-                .iter()
-                .filter_map(|row| {
+    pub fn perform_query(&mut self, plan: Plan) -> Result<Vec<Vec<String>>, QueryError> {
+        let grouped = plan.query.is_grouped();
+        let Plan {
+            query:
+                ParsedQuery {
+                    items: cols,
+                    distinct,
+                    from,
+                    group_by,
+                    aggregates,
+                    order_by,
+                    limit,
+                    offset,
+                    ..
+                },
+            filter,
+            having,
+        } = plan;
+        let dataset = self.data.get(from)?;
+        let scan_limit =
+            Driver::scan_limit(limit, offset, !order_by.is_empty() || grouped || distinct);
+        let filtered = Driver::filter(dataset.rows.iter().enumerate(), &filter, scan_limit)?;
+        let aggregated;
+        let mut records = if grouped {
+            aggregated = Driver::aggregate(&filtered, &group_by, &aggregates)?;
+            let aggregated = aggregated.iter().map(|(first, rec)| (*first, rec));
+            Driver::filter(aggregated, &having, usize::MAX)?
+        } else {
+            filtered
+        };
+        if !order_by.is_empty() {
+            Driver::sort(&mut records, &order_by)?;
+        }
+        let mut x = records
+            .iter()
+            .map(|(origin, rec)| Ok((*origin, Driver::project(rec, &cols)?)))
+            .collect::<Result<Vec<(usize, Row)>, QueryError>>()?;
+        if distinct {
+            Driver::dedup(&mut x);
+        }
+        // Columns selected as they are show the dataset's text for them, everything else is printed from its value
+        let sources: Vec<Option<usize>> = cols
+            .iter()
+            .map(|col| match col {
+                Eval::Column(col) if !grouped => Some(*col),
+                Eval::Column(col) => group_by.get(*col).copied(),
+                _ => None,
+            })
+            .collect();
+        let x = x
+            .into_iter()
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(origin, row)| {
+                row.iter()
+                    .zip(sources.iter())
+                    .map(|(val, source)| match source {
+                        Some(col) => dataset.text(origin, *col),
+                        None => val.to_string(),
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(x)
+    }
+
+    /// How many rows have to pass the filter before the rest of the dataset can be skipped
+    ///
+    /// Without an order by, grouping or distinct, the first rows that pass the filter are the ones we return,
+    /// so there is no point filtering the rest of the dataset once we have enough of them
+    fn scan_limit(limit: Option<usize>, offset: usize, needs_every_row: bool) -> usize {
+        match limit {
+            Some(limit) if !needs_every_row => limit.saturating_add(offset),
+            _ => usize::MAX,
+        }
+    }
+
+    /// Remove repeated rows, keeping the first occurrence of each
+    fn dedup(rows: &mut Vec<(usize, Row)>) {
+        let mut seen = HashSet::new();
+        rows.retain(|(_, row)| seen.insert(row.clone()));
+    }
+
+    /// Keep the records that pass the filter, stopping once `scan_limit` of them have been found
+    fn filter<'r>(
+        records: impl Iterator<Item = Record<'r>>,
+        filter: &Option<Box<dyn FilterRule>>,
+        scan_limit: usize,
+    ) -> Result<Vec<Record<'r>>, QueryError> {
+        match filter {
+            Some(filter) => records
+                .filter_map(|(origin, row)| {
                     let res = filter.filter(row);
                     match res {
+                        // Rows where the filter is unknown are dropped, like sql
                         Ok(keep) => {
-                            if keep {
-                                Some(Ok(row))
+                            if keep == Some(true) {
+                                Some(Ok((origin, row)))
                             } else {
                                 None
                             }
@@ -26,20 +128,190 @@ impl Driver {
                         Err(e) => Some(Err(e)),
                     }
                 })
-                .collect();
-            println!("after filter: {:?}", filtered);
-            let x: Vec<Vec<String>> = filtered?
-                .iter()
-                .map(|rec| rec.iter().map(|s| s.to_string()).collect())
-                .collect();
-            return Ok(x);
+                .take(scan_limit)
+                .collect(),
+            // No filter, so every record is kept
+            None => Ok(records.take(scan_limit).collect()),
         }
-        todo!()
     }
 
+    /// Hash aggregation, producing one record per group holding the group by columns followed by each aggregate
+    ///
+    /// Groups come out in the order they are first seen in the dataset.
+    /// Without a group by clause every record falls into the same group, so there is always exactly one output record
+    fn aggregate(
+        records: &[Record],
+        group_by: &[usize],
+        aggregates: &[Aggregate],
+    ) -> Result<Vec<(usize, Row)>, QueryError> {
+        let new_group =
+            || -> Vec<Accumulator> { aggregates.iter().map(|agg| agg.accumulator()).collect() };
+        let mut groups: Vec<(usize, Row, Vec<Accumulator>)> = Vec::new();
+        let mut group_index: HashMap<Row, usize> = HashMap::new();
+        if group_by.is_empty() {
+            groups.push((0, Vec::new(), new_group()));
+            group_index.insert(Vec::new(), 0);
+        }
+        for &(origin, rec) in records {
+            let key: Row = group_by.iter().map(|&col| rec[col].clone()).collect();
+            let i = match group_index.get(&key) {
+                Some(&i) => i,
+                None => {
+                    groups.push((origin, key.clone(), new_group()));
+                    group_index.insert(key, groups.len() - 1);
+                    groups.len() - 1
+                }
+            };
+            for acc in groups[i].2.iter_mut() {
+                acc.update(rec)?;
+            }
+        }
+        let aggregated = groups
+            .into_iter()
+            .map(|(origin, mut key, accs)| {
+                key.extend(accs.iter().map(|acc| acc.finish()));
+                (origin, key)
+            })
+            .collect();
+        Ok(aggregated)
+    }
+
+    /// Sort the records by each key in turn
+    ///
+    /// Values are compared by their type: numbers numerically, strings lexically and so on, see `Value::sort_cmp`.
+    /// NULLs sort after every other value, so they come last in ascending order and first in descending order.
+    /// The sort is stable, so rows that tie on every key keep their order from the dataset
+    fn sort(records: &mut Vec<Record>, order_by: &[OrderKey]) -> Result<(), QueryError> {
+        // Each record's keys are evaluated once up front, rather than on every comparison
+        let mut keyed = records
+            .drain(..)
+            .map(|rec @ (_, row)| {
+                let keys = order_by
+                    .iter()
+                    .map(|key| key.expr.eval(row).map(Cow::into_owned))
+                    .collect::<Result<Row, _>>()?;
+                Ok((keys, rec))
+            })
+            .collect::<Result<Vec<_>, QueryError>>()?;
+        keyed.sort_by(|(a, _), (b, _)| {
+            for (i, key) in order_by.iter().enumerate() {
+                let ord = a[i].sort_cmp(&b[i]);
+                let ord = if key.descending { ord.reverse() } else { ord };
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            Ordering::Equal
+        });
+        records.extend(keyed.into_iter().map(|(_, rec)| rec));
+        Ok(())
+    }
+
+    /// Evaluate the selected expressions against a record, in the order they were asked for
+    fn project(rec: &Row, cols: &[Eval]) -> Result<Row, QueryError> {
+        cols.iter()
+            .map(|col| col.eval(rec).map(Cow::into_owned))
+            .collect()
+    }
+
+    pub fn catalog(&self) -> &dyn Catalog {
+        self.data.catalog()
+    }
+
+    /// One row per column of the dataset, holding its name and type
     pub fn new() -> Result<Driver, QueryError> {
         Ok(Driver {
             data: DataAccessor::new()?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use crate::{
+        filter::{CompareFilter, CompareOp},
+        value::Value,
+    };
+
+    use super::*;
+
+    fn key(col: usize, descending: bool) -> OrderKey {
+        OrderKey {
+            expr: Eval::Column(col),
+            descending,
+        }
+    }
+
+    /// The dataset rows the records came from, in the order they were sorted into
+    fn sorted(rows: &[Row], order_by: &[OrderKey]) -> Vec<usize> {
+        let mut records: Vec<Record> = rows.iter().enumerate().collect();
+        Driver::sort(&mut records, order_by).unwrap();
+        records.iter().map(|(origin, _)| *origin).collect()
+    }
+
+    #[test]
+    fn ties_keep_their_order_from_the_dataset() {
+        let rows = vec![
+            vec![Value::Int(2), Value::Int(0)],
+            vec![Value::Int(1), Value::Int(1)],
+            vec![Value::Int(2), Value::Int(2)],
+            vec![Value::Int(1), Value::Int(3)],
+            vec![Value::Int(2), Value::Int(4)],
+        ];
+        assert_eq!(sorted(&rows, &[key(0, false)]), [1, 3, 0, 2, 4]);
+        assert_eq!(sorted(&rows, &[key(0, true)]), [0, 2, 4, 1, 3]);
+        // Later keys only break the ties of earlier ones
+        assert_eq!(
+            sorted(&rows, &[key(0, false), key(1, true)]),
+            [3, 1, 4, 2, 0]
+        );
+    }
+
+    #[test]
+    fn nulls_sort_last_ascending_and_first_descending() {
+        let rows = vec![
+            vec![Value::Float(2.5)],
+            vec![Value::Null],
+            vec![Value::Int(1)],
+            vec![Value::Null],
+            vec![Value::Int(3)],
+        ];
+        assert_eq!(sorted(&rows, &[key(0, false)]), [2, 0, 4, 1, 3]);
+        assert_eq!(sorted(&rows, &[key(0, true)]), [1, 3, 4, 0, 2]);
+    }
+
+    #[test]
+    fn scan_stops_once_limit_and_offset_rows_pass() {
+        assert_eq!(Driver::scan_limit(Some(2), 1, false), 3);
+        assert_eq!(Driver::scan_limit(Some(2), usize::MAX, false), usize::MAX);
+        assert_eq!(Driver::scan_limit(Some(2), 1, true), usize::MAX);
+        assert_eq!(Driver::scan_limit(None, 1, false), usize::MAX);
+
+        // The string would fail the comparison, but the scan stops before reaching it
+        let rows = [
+            vec![Value::Int(1)],
+            vec![Value::Int(0)],
+            vec![Value::Int(2)],
+            vec![Value::Int(3)],
+            vec![Value::Str(String::from("x"))],
+        ];
+        let filter: Option<Box<dyn FilterRule>> = Some(Box::new(CompareFilter {
+            left: Eval::Column(0),
+            op: CompareOp::GT,
+            right: Eval::Literal(Value::Int(0)),
+        }));
+        let scanned = Cell::new(0);
+        let records = rows
+            .iter()
+            .enumerate()
+            .inspect(|_| scanned.set(scanned.get() + 1));
+        let kept = Driver::filter(records, &filter, 3).unwrap();
+        let kept: Vec<usize> = kept.iter().map(|(origin, _)| *origin).collect();
+        assert_eq!(kept, [0, 2, 3]);
+        assert_eq!(scanned.get(), 4);
+
+        assert!(Driver::filter(rows.iter().enumerate(), &filter, usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use std::borrow::Cow;
+
+use crate::{
+    value::{Row, Value},
+    QueryError,
+};
+
+#[derive(Debug, Copy, Clone)]
+pub enum ArithmeticOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+/// A value expression compiled against the records it runs on, so its columns are indices into them
+///
+/// Arithmetic follows sql: NULL in gives NULL out, and int division truncates.
+/// Int results that overflow widen to floats, and results that have no finite value, like division by zero, are NULL
+#[derive(Debug)]
+pub enum Eval {
+    Column(usize),
+    Literal(Value),
+    Negate(Box<Eval>),
+    Arithmetic {
+        left: Box<Eval>,
+        op: ArithmeticOp,
+        right: Box<Eval>,
+    },
+}
+
+impl Eval {
+    /// Columns and literals are borrowed, only computed values are allocated
+    pub fn eval<'a>(&'a self, x: &'a Row) -> Result<Cow<'a, Value>, QueryError> {
+        match self {
+            Eval::Column(col) => Ok(Cow::Borrowed(&x[*col])),
+            Eval::Literal(val) => Ok(Cow::Borrowed(val)),
+            Eval::Negate(expr) => {
+                let val = expr.eval(x)?;
+                let res = match val.expect_number()? {
+                    None => Value::Null,
+                    Some(Value::Int(i)) => i
+                        .checked_neg()
+                        .map_or(Value::Float(-(*i as f64)), Value::Int),
+                    Some(num) => Value::Float(-num.as_f64().unwrap_or_default()),
+                };
+                Ok(Cow::Owned(res))
+            }
+            Eval::Arithmetic { left, op, right } => {
+                let (left, right) = (left.eval(x)?, right.eval(x)?);
+                let res = match (left.expect_number()?, right.expect_number()?) {
+                    (Some(a), Some(b)) => arithmetic(a, *op, b),
+                    _ => Value::Null,
+                };
+                Ok(Cow::Owned(res))
+            }
+        }
+    }
+}
+
+fn arithmetic(left: &Value, op: ArithmeticOp, right: &Value) -> Value {
+    if let (Value::Int(a), Value::Int(b)) = (left, right) {
+        let res = match op {
+            ArithmeticOp::Add => a.checked_add(*b),
+            ArithmeticOp::Sub => a.checked_sub(*b),
+            ArithmeticOp::Mul => a.checked_mul(*b),
+            ArithmeticOp::Div if *b == 0 => return Value::Null,
+            ArithmeticOp::Div => a.checked_div(*b),
+            ArithmeticOp::Mod if *b == 0 => return Value::Null,
+            ArithmeticOp::Mod => a.checked_rem(*b),
+        };
+        if let Some(res) = res {
+            return Value::Int(res);
+        }
+    }
+    let (a, b) = match (left.as_f64(), right.as_f64()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return Value::Null,
+    };
+    let res = match op {
+        ArithmeticOp::Add => a + b,
+        ArithmeticOp::Sub => a - b,
+        ArithmeticOp::Mul => a * b,
+        ArithmeticOp::Div => a / b,
+        ArithmeticOp::Mod => a % b,
+    };
+    if res.is_finite() {
+        Value::Float(res)
+    } else {
+        Value::Null
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(val: Value) -> Box<Eval> {
+        Box::new(Eval::Literal(val))
+    }
+
+    /// Compares by Debug, so an int result doesn't pass for an equal float
+    fn assert_evals_to(eval: &Eval, expected: Value) {
+        let row = vec![];
+        let res = eval.eval(&row).unwrap();
+        assert_eq!(
+            format!("{:?}", res),
+            format!("{:?}", expected),
+            "{:?}",
+            eval
+        );
+    }
+
+    #[test]
+    fn arithmetic_follows_sql() {
+        use ArithmeticOp::*;
+        let max = i64::MAX as f64;
+        let cases = [
+            (Value::Int(7), Div, Value::Int(2), Value::Int(3)),
+            (Value::Int(-7), Div, Value::Int(2), Value::Int(-3)),
+            (Value::Int(-7), Mod, Value::Int(2), Value::Int(-1)),
+            (Value::Int(7), Div, Value::Float(2.0), Value::Float(3.5)),
+            (Value::Int(1), Add, Value::Float(0.5), Value::Float(1.5)),
+            (Value::Int(1), Div, Value::Int(0), Value::Null),
+            (Value::Int(1), Mod, Value::Int(0), Value::Null),
+            (Value::Float(1.0), Div, Value::Float(0.0), Value::Null),
+            (Value::Float(1.0), Mod, Value::Int(0), Value::Null),
+            (
+                Value::Int(i64::MAX),
+                Add,
+                Value::Int(1),
+                Value::Float(max + 1.0),
+            ),
+            (
+                Value::Int(i64::MIN),
+                Sub,
+                Value::Int(1),
+                Value::Float(-max - 1.0),
+            ),
+            (
+                Value::Int(i64::MAX),
+                Mul,
+                Value::Int(2),
+                Value::Float(max * 2.0),
+            ),
+            (Value::Int(i64::MIN), Div, Value::Int(-1), Value::Float(max)),
+            (Value::Null, Add, Value::Int(1), Value::Null),
+            (Value::Int(1), Mul, Value::Null, Value::Null),
+            (Value::Null, Div, Value::Int(0), Value::Null),
+        ];
+        for (left, op, right, expected) in cases {
+            let eval = Eval::Arithmetic {
+                left: literal(left),
+                op,
+                right: literal(right),
+            };
+            assert_evals_to(&eval, expected);
+        }
+    }
+
+    #[test]
+    fn negating_widens_only_when_it_has_to() {
+        let cases = [
+            (Value::Int(3), Value::Int(-3)),
+            (Value::Int(i64::MAX), Value::Int(-i64::MAX)),
+            (Value::Int(i64::MIN), Value::Float(-(i64::MIN as f64))),
+            (Value::Float(2.5), Value::Float(-2.5)),
+            (Value::Null, Value::Null),
+        ];
+        for (val, expected) in cases {
+            assert_evals_to(&Eval::Negate(literal(val)), expected);
+        }
+    }
+
+    #[test]
+    fn arithmetic_on_text_fails() {
+        let eval = Eval::Arithmetic {
+            left: literal(Value::Int(1)),
+            op: ArithmeticOp::Add,
+            right: literal(Value::Str(String::from("a"))),
+        };
+        assert!(eval.eval(&vec![]).is_err());
+    }
+}
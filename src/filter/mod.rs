@@ -1,52 +1,155 @@
-use std::fmt::Debug;
+use std::{borrow::Cow, cmp::Ordering, collections::HashSet, fmt::Debug};
 
-use csv::StringRecord;
+use regex::Regex;
 
-use crate::QueryError;
+use crate::{
+    eval::Eval,
+    value::{Row, Value},
+    QueryError,
+};
 
+/// Filters use sql's three valued logic, returning Ok(None) when the result is unknown because a value was NULL.
+/// Only rows where the filter is Some(true) are kept
 pub trait FilterRule: Debug {
-    fn filter(&self, x: &StringRecord) -> Result<bool, QueryError>;
+    fn filter(&self, x: &Row) -> Result<Option<bool>, QueryError>;
 }
+
+/// The value as text, for string comparisons and pattern matching. None if NULL
+fn get_text(val: &Value) -> Option<Cow<'_, str>> {
+    match val {
+        Value::Null => None,
+        Value::Str(s) => Some(Cow::Borrowed(s)),
+        val => Some(Cow::Owned(val.to_string())),
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
-pub enum NumberOp {
+pub enum CompareOp {
     LT,
     LEQ,
     GT,
     GEQ,
     EQ,
+    NEQ,
+}
+/// Numbers compare numerically. Anything else compares by its text, lexicographically by unicode code point,
+/// and comparing a number against text fails
+#[derive(Debug)]
+pub struct CompareFilter {
+    pub left: Eval,
+    pub op: CompareOp,
+    pub right: Eval,
+}
+
+impl FilterRule for CompareFilter {
+    fn filter(&self, x: &Row) -> Result<Option<bool>, QueryError> {
+        let (left, right) = (self.left.eval(x)?, self.right.eval(x)?);
+        let ord = match (left.as_ref(), right.as_ref()) {
+            (Value::Null, _) | (_, Value::Null) => return Ok(None),
+            (a, b) if a.is_number() && b.is_number() => a.compare(b),
+            (a, b) if !a.is_number() && !b.is_number() => {
+                get_text(a).zip(get_text(b)).map(|(a, b)| a.cmp(&b))
+            }
+            _ => {
+                return Err(QueryError::QueryFailed(
+                    "Expected both sides of a comparison to be numbers or both to be text",
+                ))
+            }
+        };
+        Ok(ord.map(|ord| match self.op {
+            CompareOp::EQ => ord == Ordering::Equal,
+            CompareOp::LT => ord == Ordering::Less,
+            CompareOp::LEQ => ord != Ordering::Greater,
+            CompareOp::GT => ord == Ordering::Greater,
+            CompareOp::GEQ => ord != Ordering::Less,
+            CompareOp::NEQ => ord != Ordering::Equal,
+        }))
+    }
+}
+/// The values of an `in (...)` list, which all have to be the same type
+#[derive(Debug)]
+pub enum InList {
+    Strings(HashSet<String>),
+    Numbers(HashSet<Value>),
+}
+#[derive(Debug)]
+pub struct InFilter {
+    pub expr: Eval,
+    pub vals: InList,
+}
+
+impl FilterRule for InFilter {
+    fn filter(&self, x: &Row) -> Result<Option<bool>, QueryError> {
+        let val = self.expr.eval(x)?;
+        match &self.vals {
+            InList::Strings(vals) => Ok(get_text(&val).map(|s| vals.contains(s.as_ref()))),
+            InList::Numbers(vals) => Ok(val.expect_number()?.map(|num| vals.contains(num))),
+        }
+    }
+}
+/// Inclusive on both ends, like sql
+#[derive(Debug)]
+pub struct BetweenFilter {
+    pub expr: Eval,
+    pub low: Value,
+    pub high: Value,
+}
+
+impl FilterRule for BetweenFilter {
+    fn filter(&self, x: &Row) -> Result<Option<bool>, QueryError> {
+        let val = self.expr.eval(x)?;
+        Ok(val.expect_number()?.map(|num| {
+            num.sort_cmp(&self.low) != Ordering::Less
+                && num.sort_cmp(&self.high) != Ordering::Greater
+        }))
+    }
 }
+/// Sql `like` pattern, where `%` matches any run of characters and `_` matches exactly one
 #[derive(Debug)]
-pub struct NumberFilter {
-    pub col: usize,
-    pub op: NumberOp,
-    pub val: usize,
-}
-
-impl FilterRule for NumberFilter {
-    fn filter(&self, x: &StringRecord) -> Result<bool, QueryError> {
-        let num = x[self.col].parse::<usize>().map_err(|_| {
-            QueryError::QueryFailed(
-                "Expected a number in this column but got something else instead",
-            )
-        })?;
-        match self.op {
-            NumberOp::EQ => Ok(num == self.val),
-            NumberOp::LT => Ok(num < self.val),
-            NumberOp::LEQ => Ok(num <= self.val),
-            NumberOp::GT => Ok(num > self.val),
-            NumberOp::GEQ => Ok(num >= self.val),
+pub struct LikeFilter {
+    pub expr: Eval,
+    re: Regex,
+}
+
+impl LikeFilter {
+    pub fn new(
+        expr: Eval,
+        pattern: &str,
+        case_insensitive: bool,
+    ) -> Result<LikeFilter, QueryError> {
+        let mut re = String::from(if case_insensitive { "(?is)^" } else { "(?s)^" });
+        let mut literal = [0; 4];
+        for c in pattern.chars() {
+            match c {
+                '%' => re.push_str(".*"),
+                '_' => re.push('.'),
+                c => re.push_str(&regex::escape(c.encode_utf8(&mut literal))),
+            }
         }
+        re.push('$');
+        let re = Regex::new(&re).map_err(|e| QueryError::BadPattern(e.to_string()))?;
+        Ok(LikeFilter { expr, re })
+    }
+}
+
+impl FilterRule for LikeFilter {
+    fn filter(&self, x: &Row) -> Result<Option<bool>, QueryError> {
+        let val = self.expr.eval(x)?;
+        Ok(get_text(&val).map(|s| self.re.is_match(&s)))
     }
 }
+/// Matches if the regex matches anywhere in the value, use `^` and `$` to anchor it
 #[derive(Debug)]
-pub struct StringFilter {
-    pub col: usize,
-    pub val: String,
+pub struct RegexFilter {
+    pub expr: Eval,
+    pub re: Regex,
 }
 
-impl FilterRule for StringFilter {
-    fn filter(&self, x: &StringRecord) -> Result<bool, QueryError> {
-        Ok(x[self.col] == self.val)
+impl FilterRule for RegexFilter {
+    fn filter(&self, x: &Row) -> Result<Option<bool>, QueryError> {
+        let val = self.expr.eval(x)?;
+        Ok(get_text(&val).map(|s| self.re.is_match(&s)))
     }
 }
 #[derive(Debug)]
@@ -62,12 +165,213 @@ pub struct LogicalFilter {
 }
 
 impl FilterRule for LogicalFilter {
-    fn filter(&self, x: &StringRecord) -> Result<bool, QueryError> {
+    /// Short circuits: `f2` is only evaluated if `f1` doesn't already decide the result,
+    /// so errors in `f2` can't fail a row that `f1` has decided
+    ///
+    /// An unknown side only makes the result unknown if the other side doesn't decide it,
+    /// so `false and unknown` is false and `true or unknown` is true
+    fn filter(&self, x: &Row) -> Result<Option<bool>, QueryError> {
+        // The value of f1 that decides the result on its own
+        let decisive = match self.op {
+            LogicalOp::And => false,
+            LogicalOp::Or => true,
+        };
         let r1 = self.f1.filter(x)?;
+        if r1 == Some(decisive) {
+            return Ok(r1);
+        }
         let r2 = self.f2.filter(x)?;
-        match self.op {
-            LogicalOp::And => Ok(r1 && r2),
-            LogicalOp::Or => Ok(r1 || r2),
+        match (r1, r2) {
+            (_, Some(r)) if r == decisive => Ok(r2),
+            (Some(_), Some(_)) => Ok(Some(!decisive)),
+            _ => Ok(None),
+        }
+    }
+}
+#[derive(Debug)]
+pub struct NotFilter {
+    pub f: Box<dyn FilterRule>,
+}
+
+impl FilterRule for NotFilter {
+    fn filter(&self, x: &Row) -> Result<Option<bool>, QueryError> {
+        Ok(self.f.filter(x)?.map(|r| !r))
+    }
+}
+/// `is null`, or `is not null` if negated. Never unknown
+#[derive(Debug)]
+pub struct NullFilter {
+    pub expr: Eval,
+    pub negated: bool,
+}
+
+impl FilterRule for NullFilter {
+    fn filter(&self, x: &Row) -> Result<Option<bool>, QueryError> {
+        Ok(Some(self.expr.eval(x)?.is_null() != self.negated))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// Always gives the same result, and counts how many times it was evaluated
+    #[derive(Debug)]
+    struct Stub {
+        result: Option<bool>,
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl FilterRule for Stub {
+        fn filter(&self, _x: &Row) -> Result<Option<bool>, QueryError> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(self.result)
+        }
+    }
+
+    /// Fails the row if it is ever evaluated
+    #[derive(Debug)]
+    struct Failing;
+
+    impl FilterRule for Failing {
+        fn filter(&self, _x: &Row) -> Result<Option<bool>, QueryError> {
+            Err(QueryError::QueryFailed("evaluated the right side"))
+        }
+    }
+
+    fn stub(result: Option<bool>) -> Box<Stub> {
+        Box::new(Stub {
+            result,
+            calls: Rc::new(Cell::new(0)),
+        })
+    }
+
+    fn logical(f1: Box<dyn FilterRule>, op: LogicalOp, f2: Box<dyn FilterRule>) -> LogicalFilter {
+        LogicalFilter { f1, op, f2 }
+    }
+
+    #[test]
+    fn false_and_never_evaluates_the_right_side() {
+        let rule = logical(stub(Some(false)), LogicalOp::And, Box::new(Failing));
+        assert_eq!(rule.filter(&vec![]).unwrap(), Some(false));
+    }
+
+    #[test]
+    fn true_or_never_evaluates_the_right_side() {
+        let rule = logical(stub(Some(true)), LogicalOp::Or, Box::new(Failing));
+        assert_eq!(rule.filter(&vec![]).unwrap(), Some(true));
+    }
+
+    #[test]
+    fn undecided_left_side_evaluates_the_right_side_once() {
+        for (left, op) in [
+            (Some(true), LogicalOp::And),
+            (None, LogicalOp::And),
+            (Some(false), LogicalOp::Or),
+            (None, LogicalOp::Or),
+        ] {
+            let right = stub(Some(true));
+            let calls = Rc::clone(&right.calls);
+            let rule = logical(stub(left), op, right);
+            rule.filter(&vec![]).unwrap();
+            assert_eq!(calls.get(), 1);
+        }
+    }
+
+    #[test]
+    fn unknown_only_matters_if_the_other_side_does_not_decide() {
+        let cases = [
+            (Some(false), LogicalOp::And, None, Some(false)),
+            (None, LogicalOp::And, Some(false), Some(false)),
+            (Some(true), LogicalOp::And, None, None),
+            (None, LogicalOp::And, None, None),
+            (Some(true), LogicalOp::Or, None, Some(true)),
+            (None, LogicalOp::Or, Some(true), Some(true)),
+            (Some(false), LogicalOp::Or, None, None),
+            (None, LogicalOp::Or, None, None),
+        ];
+        for (left, op, right, expected) in cases {
+            let rule = logical(stub(left), op, stub(right));
+            assert_eq!(rule.filter(&vec![]).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn not_keeps_unknown_unknown() {
+        let rule = NotFilter { f: stub(None) };
+        assert_eq!(rule.filter(&vec![]).unwrap(), None);
+    }
+
+    fn like(pattern: &str, case_insensitive: bool, text: &str) -> Option<bool> {
+        let rule = LikeFilter::new(Eval::Column(0), pattern, case_insensitive).unwrap();
+        rule.filter(&vec![Value::Str(text.to_string())]).unwrap()
+    }
+
+    #[test]
+    fn like_wildcards_match_runs_and_single_characters() {
+        let cases = [
+            ("Zahl", "Zahl", true),
+            ("Zahl", "Zahls", false),
+            ("%ahl", "Joshua Zahl", true),
+            ("J%a%l", "Joshua Zahl", true),
+            ("%", "", true),
+            ("_", "", false),
+            ("Z_hl", "Zahl", true),
+            ("Z_hl", "Zhl", false),
+            ("caf_", "café", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                like(pattern, false, text),
+                Some(expected),
+                "'{}' like '{}'",
+                text,
+                pattern
+            );
         }
     }
+
+    #[test]
+    fn like_treats_regex_characters_literally() {
+        let cases = [
+            ("a.c", "a.c", true),
+            ("a.c", "abc", false),
+            ("(x+)", "(x+)", true),
+            ("(x+)", "xx", false),
+            ("$5 [off]%", "$5 [off] today", true),
+            ("a\\_", "a\\b", true),
+            ("^a|b", "b", false),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                like(pattern, false, text),
+                Some(expected),
+                "'{}' like '{}'",
+                text,
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn like_wildcards_match_across_lines() {
+        assert_eq!(like("first%last", false, "first\nlast"), Some(true));
+        assert_eq!(like("a_b", false, "a\nb"), Some(true));
+    }
+
+    #[test]
+    fn ilike_ignores_case() {
+        assert_eq!(like("%zahl", false, "Joshua Zahl"), Some(false));
+        assert_eq!(like("%zahl", true, "Joshua Zahl"), Some(true));
+        assert_eq!(like("ÉCOLE%", true, "école 42"), Some(true));
+        assert_eq!(like("%zahl", true, "Joshua Zahn"), Some(false));
+    }
+
+    #[test]
+    fn like_on_null_is_unknown() {
+        let rule = LikeFilter::new(Eval::Column(0), "%", false).unwrap();
+        assert_eq!(rule.filter(&vec![Value::Null]).unwrap(), None);
+    }
 }
@@ -1,36 +1,68 @@
+mod aggregate;
 mod data;
 mod driver;
+mod eval;
 mod filter;
 mod parser;
+mod planner;
+mod value;
 
 use driver::Driver;
-use parser::parser::Parser;
+use parser::{analyzer::Analyzer, diagnostic::Diagnostic, parser::Parser, ParsedQuery};
+use planner::Planner;
+
+pub use aggregate::AggregateFunc;
+pub use data::{Catalog, Column, MemoryCatalog, Schema};
+pub use parser::{ast, Span, Statement};
+pub use value::{ColumnType, Date, Value};
 
 pub struct QueryResult {
+    headers: Vec<String>,
     data: Vec<Vec<String>>,
     query: String,
 }
 
+impl QueryResult {
+    /// Names of the projected columns, in the same order as the values in each row.
+    /// A column's name is its alias if it was given one, otherwise the expression in the canonical form `format_query` prints,
+    /// so `avg*1.1` is named `avg * 1.1` however it was spaced
+    pub fn headers(&self) -> &Vec<String> {
+        &self.headers
+    }
+
+    pub fn rows(&self) -> &Vec<Vec<String>> {
+        &self.data
+    }
+}
+
+fn write_row(f: &mut std::fmt::Formatter<'_>, row: &[String]) -> std::fmt::Result {
+    write!(f, "[")?;
+    write!(f, "{}", row.first().unwrap())?; // Queries that display 0 columns are invalid
+    for column in row.iter().skip(1) {
+        write!(f, ",{}", column)?;
+    }
+    writeln!(f, "]")
+}
+
 impl std::fmt::Display for QueryResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "'{}':\n", self.query)?;
+        writeln!(f, "'{}':", self.query)?;
+        write_row(f, &self.headers)?;
         for row in self.data.iter() {
-            write!(f, "[")?;
-            write!(f, "{}", row.first().unwrap())?; // Queries that display 0 columns are invalid
-            for column in row.iter().skip(1) {
-                write!(f, ",{}", column)?;
-            }
-            write!(f, "]\n")?;
+            write_row(f, row)?;
         }
         Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum QueryError {
-    BadSyntax(&'static str), // use codespan eventually
-    BadLex(&'static str),
+    BadSyntax(Diagnostic),
+    BadLex(Diagnostic),
+    /// Every syntax and resolution error in a query that had more than one, in the order they appear in the query
+    BadQuery(Vec<Diagnostic>),
     NumParseError(String),
+    BadPattern(String),
 
     QueryFailed(&'static str),
 
@@ -38,20 +70,163 @@ pub enum QueryError {
     BadCSV(String),
 }
 
+impl QueryError {
+    /// Combines the errors found in one pass over a query, several errors are reported together as BadQuery
+    pub(crate) fn from_errors(mut errors: Vec<QueryError>) -> QueryError {
+        if errors.len() == 1 {
+            return errors.remove(0);
+        }
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        for e in errors {
+            let found = match e {
+                QueryError::BadSyntax(diagnostic) | QueryError::BadLex(diagnostic) => {
+                    vec![diagnostic]
+                }
+                QueryError::BadQuery(more) => more,
+                // Parsing and analysis only fail without a location when the parser has a bug,
+                // which is reported on its own rather than as part of the query's errors
+                e => return e,
+            };
+            // An alias used in order by or having brings its expression along with it, and any error in it too
+            for diagnostic in found {
+                let seen = diagnostics
+                    .iter()
+                    .any(|x| x.span == diagnostic.span && x.message == diagnostic.message);
+                if !seen {
+                    diagnostics.push(diagnostic);
+                }
+            }
+        }
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        if diagnostics.len() == 1 {
+            return QueryError::BadSyntax(diagnostics.remove(0));
+        }
+        QueryError::BadQuery(diagnostics)
+    }
+
+    /// Where the error happened, for errors that point into the query
+    pub fn diagnostic(&self) -> Option<&Diagnostic> {
+        match self {
+            QueryError::BadSyntax(diagnostic) | QueryError::BadLex(diagnostic) => Some(diagnostic),
+            _ => None,
+        }
+    }
+
+    /// Attach the query text to errors that point into it, so they can show where they happened
+    fn with_source(self, source: &str) -> QueryError {
+        match self {
+            QueryError::BadSyntax(diagnostic) => {
+                QueryError::BadSyntax(diagnostic.with_source(source))
+            }
+            QueryError::BadLex(diagnostic) => QueryError::BadLex(diagnostic.with_source(source)),
+            QueryError::BadQuery(diagnostics) => QueryError::BadQuery(
+                diagnostics
+                    .into_iter()
+                    .map(|diagnostic| diagnostic.with_source(source))
+                    .collect(),
+            ),
+            e => e,
+        }
+    }
+}
+
 impl std::fmt::Display for QueryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        std::fmt::Debug::fmt(self, f)
+        match self {
+            QueryError::BadSyntax(diagnostic) => write!(f, "BadSyntax: {}", diagnostic),
+            QueryError::BadLex(diagnostic) => write!(f, "BadLex: {}", diagnostic),
+            QueryError::BadQuery(diagnostics) => {
+                write!(f, "BadQuery: {} errors", diagnostics.len())?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n\n{}", diagnostic)?;
+                }
+                Ok(())
+            }
+            _ => std::fmt::Debug::fmt(self, f),
+        }
+    }
+}
+
+/// Parses a query into its syntax tree without running it or looking at any dataset
+pub fn parse(input_query: &str) -> Result<Statement, QueryError> {
+    let (statement, errors) = Parser::new(input_query).parse();
+    match statement {
+        Some(statement) if errors.is_empty() => Ok(statement),
+        _ => Err(QueryError::from_errors(errors).with_source(input_query)),
     }
 }
 
+/// Rewrites the query in canonical form, with lowercase keywords, single spaces and only the parentheses it needs.
+/// Parsing the formatted query gives the same syntax tree as parsing the original, as compared by `Statement::same_tree`
+pub fn format_query(input_query: &str) -> Result<String, QueryError> {
+    parse(input_query).map(|statement| statement.to_string())
+}
+
 pub fn perform_query(input_query: String) -> Result<QueryResult, QueryError> {
-    let mut parser = Parser::new(&input_query);
-    let parsed_query = parser.parse()?;
-    println!("query: {:?}", parsed_query);
-    let mut driver = Driver::new()?;
-    let data = driver.perform_query(parsed_query)?;
+    let (headers, data) = run_query(&input_query).map_err(|e| e.with_source(&input_query))?;
     Ok(QueryResult {
+        headers,
         data,
         query: input_query,
     })
 }
+
+/// Checks the query's syntax and resolves its names and types against the catalog, without reading any data
+///
+/// Every error found is returned at once, so editors can use this to check queries as they are written
+pub fn check_query(input_query: &str, catalog: &dyn Catalog) -> Result<(), QueryError> {
+    analyze(input_query, catalog)
+        .map(|_| ())
+        .map_err(|e| e.with_source(input_query))
+}
+
+/// A statement that has been parsed and, if it is a select, resolved against the catalog
+enum Analyzed {
+    Select(Box<ParsedQuery>),
+    /// The columns of the described dataset
+    Describe(Vec<Column>),
+}
+
+fn analyze(input_query: &str, catalog: &dyn Catalog) -> Result<Analyzed, QueryError> {
+    let (statement, mut errors) = Parser::new(input_query).parse();
+    match statement {
+        Some(Statement::Select(select)) => {
+            // This runs even if the query had syntax errors, so that unknown columns are reported along with them
+            match Analyzer::new(catalog).analyze(*select) {
+                Ok(parsed_query) if errors.is_empty() => {
+                    Ok(Analyzed::Select(Box::new(parsed_query)))
+                }
+                Ok(_) => Err(QueryError::from_errors(errors)),
+                Err(e) => {
+                    errors.push(e);
+                    Err(QueryError::from_errors(errors))
+                }
+            }
+        }
+        _ if !errors.is_empty() => Err(QueryError::from_errors(errors)),
+        Some(Statement::Describe { dataset, span }) => Analyzer::new(catalog)
+            .schema(&dataset, span)
+            .map(|schema| Analyzed::Describe(schema.columns.clone())),
+        None => unreachable!("The parser always returns a statement or errors"),
+    }
+}
+
+/// Returns the header row and the rows of the result
+fn run_query(input_query: &str) -> Result<(Vec<String>, Vec<Vec<String>>), QueryError> {
+    let mut driver = Driver::new()?;
+    let (headers, data) = match analyze(input_query, driver.catalog())? {
+        Analyzed::Select(parsed_query) => {
+            let headers = parsed_query.col_names.clone();
+            let plan = Planner::plan(*parsed_query)?;
+            (headers, driver.perform_query(plan)?)
+        }
+        Analyzed::Describe(columns) => (
+            vec![String::from("column"), String::from("type")],
+            columns
+                .iter()
+                .map(|col| vec![col.name.clone(), col.kind.name().to_string()])
+                .collect(),
+        ),
+    };
+    Ok((headers, data))
+}
@@ -0,0 +1,800 @@
+use regex::Regex;
+
+use crate::{
+    aggregate::{Aggregate, AggregateFunc},
+    data::{Catalog, Column, Schema},
+    eval::{ArithmeticOp, Eval},
+    filter::{CompareOp, LogicalOp},
+    value::ColumnType,
+    QueryError,
+};
+
+use super::{
+    ast::{BinaryOp, ColumnRef, Expr, Literal, OrderItem, Select, SelectItem},
+    diagnostic::{bad_syntax, closest, Diagnostic},
+    Condition, OrderKey, ParsedQuery, Span,
+};
+
+/// Resolves the names in a parsed select against the catalog and checks the types of every operand,
+/// producing the query the planner builds the driver's filters from
+///
+/// Everything that can be wrong with a query's names, types and patterns is caught here
+pub struct Analyzer<'a> {
+    catalog: &'a dyn Catalog,
+}
+
+impl<'a> Analyzer<'a> {
+    pub fn analyze(&self, select: Select) -> Result<ParsedQuery, QueryError> {
+        let Select {
+            items,
+            distinct,
+            from,
+            from_span,
+            filter,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+        } = select;
+        let schema = self.schema(&from, from_span)?;
+        let items = Analyzer::expand_wildcards(items, &from, &schema.columns)?;
+
+        let mut scope = Scope {
+            columns: &schema.columns,
+            grouped: false,
+            in_where: true,
+            group_by: Vec::new(),
+            aggregates: Vec::new(),
+            errors: Vec::new(),
+        };
+        // The where clause always sees the dataset's records, so it is resolved before the scope becomes grouped
+        let filter = filter.and_then(|filter| scope.condition(&filter));
+        scope.in_where = false;
+        for col in group_by.iter() {
+            let col = scope.header_index(col);
+            if let Some(col) = scope.check(col) {
+                scope.group_by.push(col);
+            }
+        }
+        scope.grouped = !group_by.is_empty()
+            || items
+                .iter()
+                .any(|item| matches!(item, SelectItem::Expr { expr, .. } if expr.has_aggregate()));
+        let aliases = aliases(&items);
+        let aliases = scope.check(aliases).unwrap_or_default();
+
+        let col_names = items.iter().map(|item| item.name()).collect();
+        let mut exprs = Vec::new();
+        for item in items {
+            let expr = scope.resolve_item(item);
+            exprs.extend(scope.check(expr));
+        }
+        // Order by and having can refer to the select list's aliases, which stand for the aliased expressions
+        let having = having.map(|mut having| {
+            replace_aliases(&mut having, &aliases);
+            having
+        });
+        let order_by: Vec<OrderItem> = order_by
+            .into_iter()
+            .map(|mut key| {
+                replace_aliases(&mut key.expr, &aliases);
+                key
+            })
+            .collect();
+        let having = match having {
+            Some(having) if !scope.grouped => {
+                scope.errors.push(bad_syntax(
+                    "Having filters groups, so it needs a group by clause or aggregates in the select list",
+                    having.span(),
+                ));
+                None
+            }
+            having => having.and_then(|having| scope.condition(&having)),
+        };
+        let mut keys = Vec::new();
+        for key in order_by {
+            let res = scope.resolve_value(&key.expr);
+            if let Some((_, expr)) = scope.check(res) {
+                keys.push(OrderKey {
+                    expr,
+                    descending: key.descending,
+                });
+            }
+        }
+        if !scope.errors.is_empty() {
+            return Err(QueryError::from_errors(scope.errors));
+        }
+        Ok(ParsedQuery {
+            items: exprs,
+            col_names,
+            distinct,
+            from,
+            filter,
+            group_by: scope.group_by,
+            aggregates: scope.aggregates,
+            having,
+            order_by: keys,
+            limit,
+            offset,
+        })
+    }
+
+    pub fn new<'b>(catalog: &'b dyn Catalog) -> Analyzer<'b> {
+        Analyzer { catalog }
+    }
+
+    /// The schema of the dataset named at the span, suggesting the closest dataset if there is none
+    pub fn schema(&self, dataset: &str, span: Span) -> Result<&'a Schema, QueryError> {
+        self.catalog.get(dataset).ok_or_else(|| {
+            let diagnostic = Diagnostic::new(format!("Unknown dataset '{}'", dataset), span);
+            QueryError::BadSyntax(
+                match closest(dataset, self.catalog.datasets().into_iter()) {
+                    Some(name) => diagnostic.with_help(format!("did you mean '{}'?", name)),
+                    None => diagnostic,
+                },
+            )
+        })
+    }
+
+    /// Replace `*` and `<dataset>.*` in the select list with every column of the dataset, in file order
+    fn expand_wildcards(
+        select: Vec<SelectItem>,
+        from: &str,
+        columns: &[Column],
+    ) -> Result<Vec<SelectItem>, QueryError> {
+        let mut expanded = Vec::new();
+        for item in select {
+            match item {
+                SelectItem::Wildcard {
+                    dataset: Some(dataset),
+                    span,
+                } if dataset != from => {
+                    return Err(bad_syntax(
+                        format!(
+                            "Qualified wildcard names '{}', not the dataset being selected from",
+                            dataset
+                        ),
+                        span,
+                    ))
+                }
+                // The expanded columns point back at the wildcard they came from
+                SelectItem::Wildcard { span, .. } => {
+                    expanded.extend(columns.iter().map(|x| SelectItem::Expr {
+                        expr: Expr::Column(ColumnRef {
+                            name: x.name.clone(),
+                            span,
+                        }),
+                        alias: None,
+                    }))
+                }
+                _ => expanded.push(item),
+            }
+        }
+        Ok(expanded)
+    }
+}
+
+/// Resolves column references that come after the from clause into indices of the records the driver works on
+///
+/// Plain queries work on the dataset's records directly. Grouped queries work on aggregated records,
+/// which hold the group by columns followed by every aggregate the query uses
+struct Scope<'c> {
+    columns: &'c [Column],
+    grouped: bool,
+    /// Whether the where clause is being resolved, which filters rows before they are grouped
+    in_where: bool,
+    group_by: Vec<usize>,
+    aggregates: Vec<Aggregate>,
+    /// Errors found so far, resolution carries on past them so they can all be reported at once
+    errors: Vec<QueryError>,
+}
+
+impl<'c> Scope<'c> {
+    fn check<T>(&mut self, res: Result<T, QueryError>) -> Option<T> {
+        match res {
+            Ok(x) => Some(x),
+            Err(e) => {
+                self.errors.push(e);
+                None
+            }
+        }
+    }
+
+    fn header_index(&self, col: &ColumnRef) -> Result<usize, QueryError> {
+        self.columns
+            .iter()
+            .position(|x| x.name == col.name)
+            .ok_or_else(|| {
+                let diagnostic =
+                    Diagnostic::new(format!("Unknown column '{}'", col.name), col.span);
+                let names = self.columns.iter().map(|x| x.name.as_str());
+                QueryError::BadSyntax(match closest(&col.name, names) {
+                    Some(name) => diagnostic.with_help(format!("did you mean '{}'?", name)),
+                    None => diagnostic,
+                })
+            })
+    }
+
+    /// The type of the values at a resolved index
+    fn col_type(&self, col: usize) -> ColumnType {
+        if !self.grouped {
+            self.columns[col].kind
+        } else if col < self.group_by.len() {
+            self.columns[self.group_by[col]].kind
+        } else {
+            let aggregate = &self.aggregates[col - self.group_by.len()];
+            aggregate.result_type(aggregate.col.map(|x| self.columns[x].kind))
+        }
+    }
+
+    fn resolve_item(&mut self, item: SelectItem) -> Result<Eval, QueryError> {
+        match item {
+            SelectItem::Expr { expr, .. } => self.resolve_value(&expr).map(|(_, eval)| eval),
+            SelectItem::Wildcard { span, .. } => Err(bad_syntax(
+                "Wildcards can only be used in the select list",
+                span,
+            )),
+        }
+    }
+
+    /// Resolves every column and aggregate in a value expression, giving the type of its result
+    /// and the expression compiled against the records of this scope
+    ///
+    /// Arithmetic on two ints gives an int, any other arithmetic gives a float
+    fn resolve_value(&mut self, expr: &Expr) -> Result<(ColumnType, Eval), QueryError> {
+        match expr {
+            Expr::Column(_) | Expr::Aggregate { .. } => {
+                let col = self.resolve(expr)?;
+                Ok((self.col_type(col), Eval::Column(col)))
+            }
+            Expr::Literal { val, .. } => {
+                let kind = match val {
+                    Literal::Int(_) => ColumnType::Int,
+                    Literal::Float(_) => ColumnType::Float,
+                    Literal::String(_) => ColumnType::String,
+                };
+                Ok((kind, Eval::Literal(val.value())))
+            }
+            Expr::Negate { expr: operand, .. } => {
+                let (kind, eval) = self.resolve_value(operand)?;
+                check_arithmetic(operand, kind, expr.span())?;
+                Ok((kind, Eval::Negate(Box::new(eval))))
+            }
+            Expr::Binary { left, op, right } if op.is_arithmetic() => {
+                let ((left_kind, left_eval), (right_kind, right_eval)) =
+                    both(self.resolve_value(left), self.resolve_value(right))?;
+                check_arithmetic(left, left_kind, expr.span())?;
+                check_arithmetic(right, right_kind, expr.span())?;
+                let kind = if left_kind == ColumnType::Int && right_kind == ColumnType::Int {
+                    ColumnType::Int
+                } else {
+                    ColumnType::Float
+                };
+                let eval = Eval::Arithmetic {
+                    left: Box::new(left_eval),
+                    op: arithmetic_op(*op),
+                    right: Box::new(right_eval),
+                };
+                Ok((kind, eval))
+            }
+            _ => Err(bad_syntax(
+                "Conditions can only be used in where and having clauses, not as values",
+                expr.span(),
+            )),
+        }
+    }
+
+    /// Resolves a column or aggregate into an index of the records
+    fn resolve(&mut self, expr: &Expr) -> Result<usize, QueryError> {
+        match expr {
+            Expr::Column(col_ref) => {
+                let col = self.header_index(col_ref)?;
+                if self.grouped {
+                    self.group_by.iter().position(|&x| x == col).ok_or_else(|| {
+                        bad_syntax(
+                            format!(
+                                "Column '{}' must be in the group by clause or inside an aggregate, since the query is grouped",
+                                col_ref.name
+                            ),
+                            col_ref.span,
+                        )
+                    })
+                } else {
+                    Ok(col)
+                }
+            }
+            Expr::Aggregate {
+                func,
+                distinct,
+                col: col_ref,
+                span,
+            } => {
+                if self.in_where {
+                    return Err(bad_syntax(
+                        "Aggregates can't be used in the where clause, which filters rows before they are grouped. Use having to filter groups",
+                        *span,
+                    ));
+                }
+                if !self.grouped {
+                    return Err(bad_syntax(
+                        "Aggregates need a grouped query: add a group by clause or an aggregate in the select list",
+                        *span,
+                    ));
+                }
+                let col = col_ref
+                    .as_ref()
+                    .map(|col_ref| self.header_index(col_ref))
+                    .transpose()?;
+                let numeric_only = matches!(func, AggregateFunc::Sum | AggregateFunc::Avg);
+                if let (true, Some(col)) = (numeric_only, col) {
+                    let kind = self.columns[col].kind;
+                    if !kind.is_numeric() {
+                        return Err(bad_syntax(
+                            format!(
+                                "{} can only aggregate int and float columns, but '{}' is a {} column",
+                                func.name(),
+                                self.columns[col].name,
+                                kind.name()
+                            ),
+                            *span,
+                        ));
+                    }
+                }
+                let aggregate = Aggregate {
+                    func: *func,
+                    distinct: *distinct,
+                    col,
+                };
+                let pos = match self.aggregates.iter().position(|x| x == &aggregate) {
+                    Some(pos) => pos,
+                    None => {
+                        self.aggregates.push(aggregate);
+                        self.aggregates.len() - 1
+                    }
+                };
+                Ok(self.group_by.len() + pos)
+            }
+            _ => Err(bad_syntax("Expected a column or an aggregate", expr.span())),
+        }
+    }
+
+    /// Resolves a where or having clause in this scope, recording the errors in its predicates
+    ///
+    /// Both sides of a logical operator are always checked, so errors in either are recorded
+    fn condition(&mut self, filter: &Expr) -> Option<Condition> {
+        match filter {
+            Expr::Binary { left, op, right } if op.is_logical() => {
+                let (left, right) = (self.condition(left), self.condition(right));
+                Some(Condition::Logical {
+                    left: Box::new(left?),
+                    op: logical_op(*op),
+                    right: Box::new(right?),
+                })
+            }
+            Expr::Not(f) => self.condition(f).map(|f| Condition::Not(Box::new(f))),
+            predicate => {
+                let res = self.check_predicate(predicate);
+                self.check(res)
+            }
+        }
+    }
+
+    /// Checks that the predicate fits the type of the values it tests, and resolves it
+    fn check_predicate(&mut self, predicate: &Expr) -> Result<Condition, QueryError> {
+        let span = predicate.span();
+        let check_type =
+            |scope: &mut Scope, expr: &Expr, numeric: bool| -> Result<Eval, QueryError> {
+                let (kind, eval) = scope.resolve_value(expr)?;
+                if numeric {
+                    check_numeric(expr, kind, span)?;
+                } else {
+                    check_textual(expr, kind, span)?;
+                }
+                Ok(eval)
+            };
+        match predicate {
+            Expr::Binary { left, op, right } if op.is_comparison() => {
+                let ((left_kind, left_eval), (right_kind, right_eval)) =
+                    both(self.resolve_value(left), self.resolve_value(right))?;
+                if left_kind.is_numeric() != right_kind.is_numeric() {
+                    // A literal is what the user meant to compare against, so the other side is the one that's wrong
+                    let (expr, kind, other) = match left.as_ref() {
+                        Expr::Literal { .. } => (right, right_kind, left_kind),
+                        _ => (left, left_kind, right_kind),
+                    };
+                    if other.is_numeric() {
+                        check_numeric(expr, kind, span)?;
+                    } else {
+                        check_textual(expr, kind, span)?;
+                    }
+                }
+                Ok(Condition::Compare {
+                    left: left_eval,
+                    op: compare_op(*op),
+                    right: right_eval,
+                })
+            }
+            Expr::IsNull { expr, negated, .. } => Ok(Condition::IsNull {
+                expr: self.resolve_value(expr)?.1,
+                negated: *negated,
+            }),
+            Expr::In { expr, list, .. } => {
+                let numbers = list.iter().filter(|val| val.is_number()).count();
+                let expr = if numbers == list.len() {
+                    check_type(self, expr, true)?
+                } else if numbers == 0 {
+                    check_type(self, expr, false)?
+                } else {
+                    return Err(bad_syntax(
+                        "Values in an in list must be all strings or all numbers",
+                        span,
+                    ));
+                };
+                Ok(Condition::In {
+                    expr,
+                    list: list.clone(),
+                })
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => Ok(Condition::Between {
+                expr: check_type(self, expr, true)?,
+                low: low.clone(),
+                high: high.clone(),
+            }),
+            Expr::Like {
+                expr,
+                pattern,
+                case_insensitive,
+                ..
+            } => Ok(Condition::Like {
+                expr: check_type(self, expr, false)?,
+                pattern: pattern.clone(),
+                case_insensitive: *case_insensitive,
+            }),
+            Expr::Matches {
+                expr,
+                pattern,
+                pattern_span,
+                ..
+            } => {
+                let (expr, re) = both(
+                    check_type(self, expr, false),
+                    check_regex(pattern, *pattern_span),
+                )?;
+                Ok(Condition::Matches { expr, re })
+            }
+            _ => Err(bad_syntax(
+                "Expected a predicate, like a comparison of a column against a value",
+                span,
+            )),
+        }
+    }
+}
+
+/// The expression behind each alias in the select list. Aliases name the columns of the result, so they must be unique
+fn aliases(items: &[SelectItem]) -> Result<Vec<(String, Expr)>, QueryError> {
+    let mut aliases: Vec<(String, Expr)> = Vec::new();
+    for item in items {
+        if let SelectItem::Expr {
+            expr,
+            alias: Some(alias),
+        } = item
+        {
+            if aliases.iter().any(|(name, _)| *name == alias.name) {
+                return Err(bad_syntax(
+                    format!("Alias '{}' is already used in the select list", alias.name),
+                    alias.span,
+                ));
+            }
+            aliases.push((alias.name.clone(), expr.clone()));
+        }
+    }
+    Ok(aliases)
+}
+
+/// Replaces columns named by an alias with a copy of the aliased expression.
+/// An alias takes priority over a dataset column with the same name
+fn replace_aliases(expr: &mut Expr, aliases: &[(String, Expr)]) {
+    match expr {
+        Expr::Column(col) => {
+            let aliased = aliases.iter().find(|(name, _)| *name == col.name);
+            if let Some((_, aliased)) = aliased {
+                *expr = aliased.clone();
+            }
+        }
+        Expr::Aggregate { .. } | Expr::Literal { .. } => {}
+        Expr::Binary { left, right, .. } => {
+            replace_aliases(left, aliases);
+            replace_aliases(right, aliases);
+        }
+        Expr::Not(expr)
+        | Expr::Negate { expr, .. }
+        | Expr::IsNull { expr, .. }
+        | Expr::In { expr, .. }
+        | Expr::Between { expr, .. }
+        | Expr::Like { expr, .. }
+        | Expr::Matches { expr, .. } => replace_aliases(expr, aliases),
+    }
+}
+
+/// Combines the results of checking two operands, so errors in both are reported
+fn both<A, B>(
+    left: Result<A, QueryError>,
+    right: Result<B, QueryError>,
+) -> Result<(A, B), QueryError> {
+    match (left, right) {
+        (Ok(left), Ok(right)) => Ok((left, right)),
+        (Err(e), Ok(_)) | (Ok(_), Err(e)) => Err(e),
+        (Err(e1), Err(e2)) => Err(QueryError::from_errors(vec![e1, e2])),
+    }
+}
+
+fn logical_op(op: BinaryOp) -> LogicalOp {
+    match op {
+        BinaryOp::And => LogicalOp::And,
+        BinaryOp::Or => LogicalOp::Or,
+        _ => unreachable!("'{:?}' is not a logical operator", op),
+    }
+}
+
+fn compare_op(op: BinaryOp) -> CompareOp {
+    match op {
+        BinaryOp::LT => CompareOp::LT,
+        BinaryOp::LEQ => CompareOp::LEQ,
+        BinaryOp::GT => CompareOp::GT,
+        BinaryOp::GEQ => CompareOp::GEQ,
+        BinaryOp::EQ => CompareOp::EQ,
+        BinaryOp::NEQ => CompareOp::NEQ,
+        _ => unreachable!("'{:?}' is not a comparison", op),
+    }
+}
+
+fn arithmetic_op(op: BinaryOp) -> ArithmeticOp {
+    match op {
+        BinaryOp::Add => ArithmeticOp::Add,
+        BinaryOp::Sub => ArithmeticOp::Sub,
+        BinaryOp::Mul => ArithmeticOp::Mul,
+        BinaryOp::Div => ArithmeticOp::Div,
+        BinaryOp::Mod => ArithmeticOp::Mod,
+        _ => unreachable!("'{:?}' is not an arithmetic operator", op),
+    }
+}
+
+/// How an operand is described in type errors, like `'code' is a string column` or `'avg + 1' is a float value`
+fn describe(item: &Expr, kind: ColumnType) -> String {
+    let what = match item {
+        Expr::Column(_) => "column",
+        _ => "value",
+    };
+    let article = if kind.name().starts_with(|c| "aeiou".contains(c)) {
+        "an"
+    } else {
+        "a"
+    };
+    format!("'{}' is {} {} {}", item.name(), article, kind.name(), what)
+}
+
+fn check_arithmetic(item: &Expr, kind: ColumnType, span: Span) -> Result<(), QueryError> {
+    if kind.is_numeric() {
+        Ok(())
+    } else {
+        Err(bad_syntax(
+            format!(
+                "{}, arithmetic can only be done on int and float values",
+                describe(item, kind)
+            ),
+            span,
+        ))
+    }
+}
+
+/// Compiles the pattern of a `matches`, pointing at the pattern if it isn't a valid regex
+fn check_regex(pattern: &str, span: Span) -> Result<Regex, QueryError> {
+    Regex::new(pattern).map_err(|e| {
+        // The regex crate repeats the pattern with its own marker, only its last line says what is wrong
+        let e = e.to_string();
+        let reason = e.lines().last().unwrap_or_default();
+        bad_syntax(
+            format!(
+                "Invalid regex pattern, {}",
+                reason.trim_start_matches("error: ")
+            ),
+            span,
+        )
+    })
+}
+
+fn check_numeric(item: &Expr, col_type: ColumnType, span: Span) -> Result<(), QueryError> {
+    if col_type.is_numeric() {
+        Ok(())
+    } else {
+        Err(bad_syntax(
+            format!(
+                "{}, only int and float values can be compared against numbers",
+                describe(item, col_type)
+            ),
+            span,
+        ))
+    }
+}
+
+/// Strings and patterns match the text of bool, date and string columns, numeric columns need a number
+fn check_textual(item: &Expr, col_type: ColumnType, span: Span) -> Result<(), QueryError> {
+    if col_type.is_numeric() {
+        Err(bad_syntax(
+            format!(
+                "{}, so it must be compared against numbers, not strings or patterns",
+                describe(item, col_type)
+            ),
+            span,
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data::{MemoryCatalog, Schema},
+        QueryError,
+    };
+
+    use super::*;
+
+    fn catalog() -> MemoryCatalog {
+        let column = |name: &str, kind| Column {
+            name: name.to_string(),
+            kind,
+        };
+        let mut catalog = MemoryCatalog::new();
+        catalog.add(Schema {
+            name: String::from("courses"),
+            columns: vec![
+                column("code", ColumnType::Int),
+                column("instructor", ColumnType::String),
+                column("avg", ColumnType::Float),
+            ],
+        });
+        catalog
+    }
+
+    /// The message and the text it points at of every error found in the query, in order
+    fn errors(query: &str) -> Vec<(String, &str)> {
+        let found = match crate::check_query(query, &catalog()) {
+            Ok(()) => vec![],
+            Err(QueryError::BadQuery(diagnostics)) => diagnostics,
+            Err(e) => match e.diagnostic() {
+                Some(diagnostic) => vec![diagnostic.clone()],
+                None => panic!("'{}' gave an error without a location: {:?}", query, e),
+            },
+        };
+        found
+            .iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.message.clone(),
+                    &query[diagnostic.span.start..diagnostic.span.end],
+                )
+            })
+            .collect()
+    }
+
+    fn error<'q>(message: &str, text: &'q str) -> (String, &'q str) {
+        (message.to_string(), text)
+    }
+
+    #[test]
+    fn valid_queries_have_no_errors() {
+        for query in [
+            "select * from courses where instructor like \"%Zahl\" or avg between 60 and 70",
+            "select code, avg * 1.1 as curved from courses order by curved desc limit 3",
+            "select instructor, count(*) as n from courses group by instructor having n > 1 order by n",
+        ] {
+            assert_eq!(errors(query), [], "{}", query);
+        }
+    }
+
+    #[test]
+    fn unknown_names_suggest_the_closest_one() {
+        assert_eq!(
+            errors("select code from course"),
+            [error("Unknown dataset 'course'", "course")]
+        );
+        assert_eq!(
+            errors("describe cours"),
+            [error("Unknown dataset 'cours'", "cours")]
+        );
+        assert_eq!(errors("describe courses"), []);
+        match crate::check_query("select codee from courses", &catalog()) {
+            Err(QueryError::BadSyntax(diagnostic)) => {
+                assert_eq!(diagnostic.message, "Unknown column 'codee'");
+                assert_eq!(diagnostic.help.as_deref(), Some("did you mean 'code'?"));
+            }
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn type_errors_point_at_the_operation() {
+        let cases = [
+            (
+                "select code from courses where instructor > 3",
+                "'instructor' is a string column, only int and float values can be compared against numbers",
+                "instructor > 3",
+            ),
+            (
+                "select code from courses where avg like \"6%\"",
+                "'avg' is a float column, so it must be compared against numbers, not strings or patterns",
+                "avg like \"6%\"",
+            ),
+            (
+                "select code from courses where code + 1 like \"1%\"",
+                "'code + 1' is an int value, so it must be compared against numbers, not strings or patterns",
+                "code + 1 like \"1%\"",
+            ),
+            (
+                "select avg + instructor from courses",
+                "'instructor' is a string column, arithmetic can only be done on int and float values",
+                "avg + instructor",
+            ),
+            (
+                "select sum(instructor) from courses",
+                "sum can only aggregate int and float columns, but 'instructor' is a string column",
+                "sum(instructor)",
+            ),
+        ];
+        for (query, message, text) in cases {
+            assert_eq!(errors(query), [error(message, text)], "{}", query);
+        }
+    }
+
+    #[test]
+    fn aggregates_need_a_grouped_query() {
+        assert_eq!(
+            errors("select code from courses order by count(*)"),
+            [error(
+                "Aggregates need a grouped query: add a group by clause or an aggregate in the select list",
+                "count(*)"
+            )]
+        );
+        assert_eq!(
+            errors("select instructor from courses where max(avg) > 80 group by instructor"),
+            [error(
+                "Aggregates can't be used in the where clause, which filters rows before they are grouped. Use having to filter groups",
+                "max(avg)"
+            )]
+        );
+    }
+
+    #[test]
+    fn every_error_is_reported_at_once() {
+        let query = "select codee, avg from courses where instructor > 3 and avg matches \"(\" order by nope";
+        let found = errors(query);
+        let texts: Vec<&str> = found.iter().map(|(_, text)| *text).collect();
+        assert_eq!(
+            texts,
+            [
+                "codee",
+                "instructor > 3",
+                "avg matches \"(\"",
+                "\"(\"",
+                "nope"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_regexes_point_at_the_pattern() {
+        assert_eq!(
+            errors("select code from courses where instructor matches \"(\""),
+            [error("Invalid regex pattern, unclosed group", "\"(\"")]
+        );
+        assert_eq!(
+            errors("select code from courses where instructor matches \"^a+$\""),
+            []
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use serde::Serialize;
+
+use crate::{aggregate::AggregateFunc, value::Value};
+
+use super::Span;
+
+/// A select query as it was written, none of its names have been resolved against a dataset yet
+///
+/// The tree is plain data, so it can be inspected, compared and rewritten before it is planned
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Select {
+    pub items: Vec<SelectItem>,
+    pub distinct: bool,
+    pub from: String,
+    pub from_span: Span,
+    pub filter: Option<Expr>,
+    pub group_by: Vec<ColumnRef>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderItem>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+impl Select {
+    /// Resets the span of every node to the default, so only what the query means is left to compare
+    pub fn clear_spans(&mut self) {
+        self.from_span = Span::default();
+        for item in self.items.iter_mut() {
+            match item {
+                SelectItem::Expr { expr, alias } => {
+                    expr.clear_spans();
+                    if let Some(alias) = alias {
+                        alias.span = Span::default();
+                    }
+                }
+                SelectItem::Wildcard { span, .. } => *span = Span::default(),
+            }
+        }
+        for col in self.group_by.iter_mut() {
+            col.span = Span::default();
+        }
+        let clauses = self.filter.iter_mut().chain(self.having.iter_mut());
+        for expr in clauses.chain(self.order_by.iter_mut().map(|key| &mut key.expr)) {
+            expr.clear_spans();
+        }
+    }
+}
+
+/// An entry of the select list
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum SelectItem {
+    /// `<expr> as <alias>` if it has an alias
+    Expr {
+        expr: Expr,
+        alias: Option<ColumnRef>,
+    },
+    /// `*` if the dataset is None, otherwise `<dataset>.*`
+    Wildcard { dataset: Option<String>, span: Span },
+}
+
+impl SelectItem {
+    /// The name the item gets in the result headers, its alias if it has one
+    pub fn name(&self) -> String {
+        match self {
+            SelectItem::Expr {
+                alias: Some(alias), ..
+            } => alias.name.clone(),
+            SelectItem::Expr { expr, .. } => expr.name(),
+            SelectItem::Wildcard { dataset: None, .. } => String::from("*"),
+            SelectItem::Wildcard {
+                dataset: Some(dataset),
+                ..
+            } => format!("{}.*", dataset),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            SelectItem::Expr { expr, alias } => match alias {
+                Some(alias) => expr.span().to(alias.span),
+                None => expr.span(),
+            },
+            SelectItem::Wildcard { span, .. } => *span,
+        }
+    }
+}
+
+/// A column named in the query, or the alias given to a column of the select list
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnRef {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OrderItem {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+/// An expression from the select list, a where or having clause or an order by key.
+/// Either a value, or a condition built from predicates
+///
+/// Predicates that only take literals on their right, like `in` and `between`, hold them directly
+/// rather than as expressions
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Expr {
+    Column(ColumnRef),
+    /// The column is None for `count(*)`
+    Aggregate {
+        func: AggregateFunc,
+        distinct: bool,
+        col: Option<ColumnRef>,
+        span: Span,
+    },
+    Literal {
+        val: Literal,
+        span: Span,
+    },
+    /// Unary minus. Minus signs in front of number literals are part of the literal instead
+    Negate {
+        expr: Box<Expr>,
+        span: Span,
+    },
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOp,
+        right: Box<Expr>,
+    },
+    Not(Box<Expr>),
+    /// `is null`, or `is not null` if negated
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+        span: Span,
+    },
+    In {
+        expr: Box<Expr>,
+        list: Vec<Literal>,
+        span: Span,
+    },
+    Between {
+        expr: Box<Expr>,
+        /// Always an Int or a Float
+        low: Literal,
+        high: Literal,
+        span: Span,
+    },
+    Like {
+        expr: Box<Expr>,
+        pattern: String,
+        case_insensitive: bool,
+        span: Span,
+    },
+    Matches {
+        expr: Box<Expr>,
+        pattern: String,
+        /// The pattern's string, so an invalid regex can be pointed at
+        pattern_span: Span,
+        span: Span,
+    },
+}
+
+impl Expr {
+    /// The name the expression gets in the result headers, its canonical printed form
+    pub fn name(&self) -> String {
+        match self {
+            Expr::Column(col) => col.name.clone(),
+            Expr::Aggregate {
+                func,
+                distinct,
+                col,
+                ..
+            } => format!(
+                "{}({}{})",
+                func.name(),
+                if *distinct { "distinct " } else { "" },
+                col.as_ref().map_or("*", |col| col.name.as_str())
+            ),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Whether the expression is true or false rather than a value, like a predicate or a logical operator
+    pub fn is_condition(&self) -> bool {
+        match self {
+            Expr::Binary { op, .. } => !op.is_arithmetic(),
+            Expr::Column(_)
+            | Expr::Aggregate { .. }
+            | Expr::Literal { .. }
+            | Expr::Negate { .. } => false,
+            Expr::Not(_)
+            | Expr::IsNull { .. }
+            | Expr::In { .. }
+            | Expr::Between { .. }
+            | Expr::Like { .. }
+            | Expr::Matches { .. } => true,
+        }
+    }
+
+    /// Whether an aggregate appears anywhere in the expression
+    pub fn has_aggregate(&self) -> bool {
+        match self {
+            Expr::Aggregate { .. } => true,
+            Expr::Column(_) | Expr::Literal { .. } => false,
+            Expr::Binary { left, right, .. } => left.has_aggregate() || right.has_aggregate(),
+            Expr::Not(expr)
+            | Expr::Negate { expr, .. }
+            | Expr::IsNull { expr, .. }
+            | Expr::In { expr, .. }
+            | Expr::Between { expr, .. }
+            | Expr::Like { expr, .. }
+            | Expr::Matches { expr, .. } => expr.has_aggregate(),
+        }
+    }
+
+    /// Resets the span of every node in the expression to the default
+    pub fn clear_spans(&mut self) {
+        match self {
+            Expr::Column(col) => col.span = Span::default(),
+            Expr::Aggregate { col, span, .. } => {
+                if let Some(col) = col {
+                    col.span = Span::default();
+                }
+                *span = Span::default();
+            }
+            Expr::Literal { span, .. } => *span = Span::default(),
+            Expr::Binary { left, right, .. } => {
+                left.clear_spans();
+                right.clear_spans();
+            }
+            Expr::Not(expr) => expr.clear_spans(),
+            Expr::Matches {
+                expr,
+                pattern_span,
+                span,
+                ..
+            } => {
+                expr.clear_spans();
+                *pattern_span = Span::default();
+                *span = Span::default();
+            }
+            Expr::Negate { expr, span }
+            | Expr::IsNull { expr, span, .. }
+            | Expr::In { expr, span, .. }
+            | Expr::Between { expr, span, .. }
+            | Expr::Like { expr, span, .. } => {
+                expr.clear_spans();
+                *span = Span::default();
+            }
+        }
+    }
+
+    /// Covers the whole expression, but not the parentheses or `not` around the outside
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Column(col) => col.span,
+            Expr::Binary { left, right, .. } => left.span().to(right.span()),
+            Expr::Not(expr) => expr.span(),
+            Expr::Aggregate { span, .. }
+            | Expr::Literal { span, .. }
+            | Expr::Negate { span, .. }
+            | Expr::IsNull { span, .. }
+            | Expr::In { span, .. }
+            | Expr::Between { span, .. }
+            | Expr::Like { span, .. }
+            | Expr::Matches { span, .. } => *span,
+        }
+    }
+}
+
+/// `is` and `is not` are written as EQ and NEQ
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize)]
+pub enum BinaryOp {
+    And,
+    Or,
+    LT,
+    LEQ,
+    GT,
+    GEQ,
+    EQ,
+    NEQ,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl BinaryOp {
+    pub fn is_logical(&self) -> bool {
+        matches!(self, BinaryOp::And | BinaryOp::Or)
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::LT
+                | BinaryOp::LEQ
+                | BinaryOp::GT
+                | BinaryOp::GEQ
+                | BinaryOp::EQ
+                | BinaryOp::NEQ
+        )
+    }
+
+    pub fn is_arithmetic(&self) -> bool {
+        !self.is_logical() && !self.is_comparison()
+    }
+}
+
+/// A value written in the query. Unlike `Value`, ints and floats are never equal to each other,
+/// so `65` and `65.0` are different trees
+#[derive(Debug, Clone, Serialize)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl Literal {
+    pub fn is_number(&self) -> bool {
+        matches!(self, Literal::Int(_) | Literal::Float(_))
+    }
+
+    pub fn value(&self) -> Value {
+        match self {
+            Literal::Int(i) => Value::Int(*i),
+            Literal::Float(f) => Value::Float(*f),
+            Literal::String(s) => Value::Str(s.clone()),
+        }
+    }
+}
+
+/// Floats compare by their bits, so equal trees always print the same
+impl PartialEq for Literal {
+    fn eq(&self, other: &Literal) -> bool {
+        match (self, other) {
+            (Literal::Int(a), Literal::Int(b)) => a == b,
+            (Literal::Float(a), Literal::Float(b)) => a.to_bits() == b.to_bits(),
+            (Literal::String(a), Literal::String(b)) => a == b,
+            _ => false,
+        }
+    }
+}
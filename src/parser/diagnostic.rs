@@ -0,0 +1,118 @@
+use std::fmt::Display;
+
+use crate::QueryError;
+
+use super::Span;
+
+/// An error pointing at the part of the query that caused it
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    /// A suggested fix, shown under the snippet
+    pub help: Option<String>,
+    /// The query text the span points into. Only attached once the query has failed,
+    /// so the parser and analyzer don't need to carry the text around
+    source: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            message: message.into(),
+            span,
+            help: None,
+            source: None,
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_source(mut self, source: &str) -> Diagnostic {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    /// The 1-based line and column the span starts at, columns count chars rather than bytes
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.span.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, before[line_start..].chars().count() + 1)
+    }
+}
+
+/// Shorthand for the common case of a syntax error without a suggestion
+pub fn bad_syntax(message: impl Into<String>, span: Span) -> QueryError {
+    QueryError::BadSyntax(Diagnostic::new(message, span))
+}
+
+/// The candidate closest to `name` by edit distance, if it is close enough to plausibly be a typo of it
+pub fn closest<'c>(name: &str, candidates: impl Iterator<Item = &'c str>) -> Option<&'c str> {
+    let max_distance = (name.chars().count() / 2).max(1);
+    candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance, counting single char insertions, deletions and substitutions
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur.push(substitution.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Without the source this is just the message, with it we show the line and column
+/// and the line of the query underlined with carets, like
+///
+/// ```text
+/// Unknown column 'avgg' at 1:14
+///   |
+/// 1 | select code, avgg from test
+///   |              ^^^^
+///   = help: did you mean 'avg'?
+/// ```
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return write!(f, "{}", self.message),
+        };
+        let (line, col) = self.line_col(source);
+        let line_text = source.lines().nth(line - 1).unwrap_or("");
+        // Spans over several lines are only underlined up to the end of their first line
+        let underlined = source[self.span.start.min(source.len())..self.span.end.min(source.len())]
+            .lines()
+            .next()
+            .map_or(0, |text| text.chars().count())
+            .max(1);
+        let gutter = " ".repeat(line.to_string().len());
+        writeln!(f, "{} at {}:{}", self.message, line, col)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line, line_text)?;
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(col - 1),
+            "^".repeat(underlined)
+        )?;
+        if let Some(help) = &self.help {
+            write!(f, "\n{} = help: {}", gutter, help)?;
+        }
+        Ok(())
+    }
+}
@@ -2,100 +2,147 @@ use std::{iter::Peekable, str::Chars};
 
 use crate::QueryError;
 
-use super::{Token, TokenType};
+use super::{diagnostic::Diagnostic, Span, Token, TokenType};
 
 fn is_whitespace(c: &char) -> bool {
     c.is_whitespace()
 }
 
+/// A token's kind and lexemme, or the reason it couldn't be lexed. The span is added once the token is finished
+type Lexed = Result<(TokenType, Option<String>), String>;
+
 #[derive(Clone)]
 pub struct Lexer<'a> {
     iter: Peekable<Chars<'a>>,
+    /// Byte offset of the next char
+    pos: usize,
 }
 
 // Borrowed a lot of code from micro-mitten and past lexers ive written
 impl<'a> Lexer<'a> {
-    fn numeric(&mut self, first: char) -> Option<Result<Token, QueryError>> {
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.iter.next()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Lexes unsigned numbers like `65`, `65.5` and `6.55e1`, the sign is a separate token
+    fn numeric(&mut self, first: char) -> Lexed {
         let mut num = String::new();
         num.push(first);
-        while self.iter.peek()?.is_numeric() {
-            num.push(self.iter.next()?);
+        self.digits(&mut num);
+
+        if self.iter.peek() == Some(&'.') {
+            self.next_char();
+            num.push('.');
+            if !self.digits(&mut num) {
+                return Err(String::from("Expected digits after decimal point"));
+            }
         }
 
-        Ok(Token {
-            kind: TokenType::Number,
-            lexemme: Some(num),
-        })
-        .into()
+        // Only treat the e as an exponent if digits follow it
+        let mut lookahead = self.iter.clone();
+        if let Some(e @ 'e') | Some(e @ 'E') = lookahead.next() {
+            let sign = match lookahead.peek() {
+                Some(&c) if c == '+' || c == '-' => {
+                    lookahead.next();
+                    Some(c)
+                }
+                _ => None,
+            };
+            if matches!(lookahead.peek(), Some(c) if c.is_ascii_digit()) {
+                self.next_char();
+                num.push(e);
+                if let Some(sign) = sign {
+                    self.next_char();
+                    num.push(sign);
+                }
+                self.digits(&mut num);
+            }
+        }
+
+        Ok((TokenType::Number, Some(num)))
+    }
+
+    /// Pushes digits onto the string until a non-digit, returns false if there were none
+    fn digits(&mut self, num: &mut String) -> bool {
+        let mut any = false;
+        while let Some(&c) = self.iter.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            num.push(c);
+            self.next_char();
+            any = true;
+        }
+        any
     }
 
-    fn string(&mut self) -> Option<Result<Token, QueryError>> {
+    /// Lexes the rest of a string after its opening quote, a doubled quote `""` stands for a quote inside it
+    fn string(&mut self) -> Lexed {
         let mut str = String::new();
         loop {
             // Note: Might need to change this in the future, but this should work for now.
             // Strings capture all whitespace and whatever weirdness the user throws inbetween the quotes
-            match self.iter.peek()? {
-                c if !(c == &'\"') => {
-                    str.push(self.iter.next()?);
+            match self.next_char() {
+                Some('\"') if self.iter.peek() == Some(&'\"') => {
+                    self.next_char();
+                    str.push('\"');
                 }
-                _ => break,
+                Some('\"') => break,
+                Some(c) => str.push(c),
+                None => return Err(String::from("Unterminated string, expected a closing '\"'")),
             }
         }
-        self.iter.next()?; // the closing "
-        Ok(Token {
-            kind: TokenType::String,
-            lexemme: Some(str),
-        })
-        .into()
+        Ok((TokenType::String, Some(str)))
     }
 
-    fn alpha(&mut self, first: char) -> Option<Result<Token, QueryError>> {
+    fn alpha(&mut self, first: char) -> Lexed {
         let mut data = String::new();
         data.push(first);
-        loop {
-            match self.iter.peek()? {
-                c if c.is_alphanumeric() || c == &'_' || c == &'.' || c == &'*' => {
-                    data.push(self.iter.next()?);
-                }
-                _ => break,
+        while let Some(&c) = self.iter.peek() {
+            // A '*' is only part of the name in `<dataset>.*`, otherwise it is multiplication
+            let wildcard = c == '*' && data.ends_with('.');
+            if !(c.is_alphanumeric() || c == '_' || c == '.' || wildcard) {
+                break;
             }
+            data.push(c);
+            self.next_char();
         }
-        let token = match data.as_str() {
-            "select" => Token {
-                kind: TokenType::Select,
-                lexemme: None,
-            },
-            "where" => Token {
-                kind: TokenType::Where,
-                lexemme: None,
-            },
-            "from" => Token {
-                kind: TokenType::From,
-                lexemme: None,
-            },
-            "and" => Token {
-                kind: TokenType::And,
-                lexemme: None,
-            },
-            "or" => Token {
-                kind: TokenType::Or,
-                lexemme: None,
-            },
-            "is" => Token {
-                kind: TokenType::Is,
-                lexemme: None,
-            },
-            _ => Token {
-                kind: TokenType::Identifier,
-                lexemme: Some(data),
-            },
+        let kind = match data.as_str() {
+            "select" => TokenType::Select,
+            "describe" => TokenType::Describe,
+            "distinct" => TokenType::Distinct,
+            "where" => TokenType::Where,
+            "from" => TokenType::From,
+            "group" => TokenType::Group,
+            "having" => TokenType::Having,
+            "order" => TokenType::Order,
+            "by" => TokenType::By,
+            "asc" => TokenType::Asc,
+            "desc" => TokenType::Desc,
+            "limit" => TokenType::Limit,
+            "offset" => TokenType::Offset,
+            "as" => TokenType::As,
+            "and" => TokenType::And,
+            "or" => TokenType::Or,
+            "not" => TokenType::Not,
+            "like" => TokenType::Like,
+            "ilike" => TokenType::ILike,
+            "matches" => TokenType::Matches,
+            "in" => TokenType::In,
+            "between" => TokenType::Between,
+            "null" => TokenType::Null,
+            "is" => TokenType::Is,
+            _ => return Ok((TokenType::Identifier, Some(data))),
         };
-        Some(Ok(token))
+        Ok((kind, None))
     }
 
     pub fn new(query: &'a str) -> Lexer<'a> {
         Lexer {
             iter: query.chars().peekable(),
+            pos: 0,
         }
     }
 }
@@ -105,65 +152,74 @@ impl<'a> Iterator for Lexer<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         while is_whitespace(self.iter.peek()?) {
-            self.iter.next()?;
+            self.next_char();
         }
 
-        let token = match self.iter.next()? {
-            ',' => Token {
-                kind: TokenType::Comma,
-                lexemme: None,
-            }
-            .into(),
+        let start = self.pos;
+        let lexed = match self.next_char()? {
+            ',' => Ok((TokenType::Comma, None)),
+            '-' => Ok((TokenType::Minus, None)),
+            '+' => Ok((TokenType::Plus, None)),
+            '/' => Ok((TokenType::Slash, None)),
+            '%' => Ok((TokenType::Percent, None)),
+            '(' => Ok((TokenType::LeftParen, None)),
+            ')' => Ok((TokenType::RightParen, None)),
+            '*' => Ok((TokenType::Star, None)),
             '<' => {
-                let kind = if let Some('=') = self.iter.peek() {
-                    self.iter.next();
-                    TokenType::LEQ
-                } else {
-                    TokenType::LT
+                let kind = match self.iter.peek() {
+                    Some('=') => {
+                        self.next_char();
+                        TokenType::LEQ
+                    }
+                    Some('>') => {
+                        self.next_char();
+                        TokenType::NEQ
+                    }
+                    _ => TokenType::LT,
                 };
-                Token {
-                    kind,
-                    lexemme: None,
-                }
-                .into()
+                Ok((kind, None))
             }
             '>' => {
                 let kind = if let Some('=') = self.iter.peek() {
-                    self.iter.next();
+                    self.next_char();
                     TokenType::GEQ
                 } else {
                     TokenType::GT
                 };
-                Token {
-                    kind,
-                    lexemme: None,
+                Ok((kind, None))
+            }
+            '!' => {
+                if let Some('=') = self.iter.peek() {
+                    self.next_char();
+                    Ok((TokenType::NEQ, None))
+                } else {
+                    Err(String::from("Single '!' is invalid, did you mean '!='?"))
                 }
-                .into()
             }
             '=' => {
-                let kind = if let Some('=') = self.iter.peek() {
-                    self.iter.next();
-                    TokenType::EQ
+                if let Some('=') = self.iter.peek() {
+                    self.next_char();
+                    Ok((TokenType::EQ, None))
                 } else {
-                    return Some(Err(QueryError::BadLex("Single '=' is invalid")));
-                };
-                Token {
-                    kind,
-                    lexemme: None,
+                    Err(String::from("Single '=' is invalid, did you mean '=='?"))
                 }
-                .into()
             }
-            '\"' => self.string()?,
-            c if c.is_numeric() => self.numeric(c)?,
-            c if c.is_alphabetic() => self.alpha(c)?,
-            _ => Err(QueryError::BadLex("Invalid token, unable to lex")),
+            '\"' => self.string(),
+            c if c.is_ascii_digit() => self.numeric(c),
+            c if c.is_alphabetic() => self.alpha(c),
+            c => Err(format!("Unexpected character '{}', unable to lex", c)),
         };
-        Some(token)
-    }
-}
-
-impl Into<Result<Token, QueryError>> for Token {
-    fn into(self) -> Result<Token, QueryError> {
-        Ok(self)
+        let span = Span {
+            start,
+            end: self.pos,
+        };
+        Some(match lexed {
+            Ok((kind, lexemme)) => Ok(Token {
+                kind,
+                lexemme,
+                span,
+            }),
+            Err(message) => Err(QueryError::BadLex(Diagnostic::new(message, span))),
+        })
     }
 }
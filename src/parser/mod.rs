@@ -1,35 +1,173 @@
+pub mod analyzer;
+pub mod ast;
+pub mod diagnostic;
 mod lexer;
+#[allow(clippy::module_inception)]
 pub mod parser;
+mod printer;
 
 use std::fmt::{Debug, Display};
 
-use crate::filter::FilterRule;
+use regex::Regex;
+use serde::Serialize;
 
+use crate::{
+    aggregate::Aggregate,
+    eval::Eval,
+    filter::{CompareOp, LogicalOp},
+};
+
+/// A statement the repl can run, as the parser produces it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Statement {
+    Select(Box<ast::Select>),
+    /// `describe <dataset>`, lists the dataset's columns and their types
+    Describe {
+        dataset: String,
+        span: Span,
+    },
+}
+
+impl Statement {
+    /// Whether both statements have the same tree, wherever their nodes were written.
+    /// A statement is the same tree as itself reparsed from different text, like its printed form
+    pub fn same_tree(&self, other: &Statement) -> bool {
+        let (mut a, mut b) = (self.clone(), other.clone());
+        for statement in [&mut a, &mut b] {
+            match statement {
+                Statement::Select(select) => select.clear_spans(),
+                Statement::Describe { span, .. } => *span = Span::default(),
+            }
+        }
+        a == b
+    }
+}
+
+/// A query whose names have been resolved and types checked, ready to be planned
+///
+/// Every column in the select list, filters and order by keys is already an index into the records it runs on.
+/// The where clause indexes into the dataset's records. Everything after it in a grouped query indexes into
+/// the aggregated records instead, which hold the `group_by` columns followed by the `aggregates`
 #[derive(Debug)]
 pub struct ParsedQuery {
-    pub cols: Vec<usize>,
+    /// The select list with its wildcards expanded
+    pub items: Vec<Eval>,
+    pub col_names: Vec<String>,
+    pub distinct: bool,
     pub from: String,
-    pub filter: Option<Box<dyn FilterRule>>,
+    pub filter: Option<Condition>,
+    pub group_by: Vec<usize>,
+    pub aggregates: Vec<Aggregate>,
+    pub having: Option<Condition>,
+    pub order_by: Vec<OrderKey>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+/// A where or having clause with its values resolved, which the planner builds the filter rules from
+#[derive(Debug)]
+pub enum Condition {
+    Logical {
+        left: Box<Condition>,
+        op: LogicalOp,
+        right: Box<Condition>,
+    },
+    Not(Box<Condition>),
+    Compare {
+        left: Eval,
+        op: CompareOp,
+        right: Eval,
+    },
+    IsNull {
+        expr: Eval,
+        negated: bool,
+    },
+    In {
+        expr: Eval,
+        list: Vec<ast::Literal>,
+    },
+    Between {
+        expr: Eval,
+        low: ast::Literal,
+        high: ast::Literal,
+    },
+    Like {
+        expr: Eval,
+        pattern: String,
+        case_insensitive: bool,
+    },
+    /// Compiled when the pattern was checked
+    Matches {
+        expr: Eval,
+        re: Regex,
+    },
+}
+
+/// One key of an `order by` clause, earlier keys take priority over later ones
+#[derive(Debug)]
+pub struct OrderKey {
+    pub expr: Eval,
+    pub descending: bool,
+}
+
+/// A range of byte offsets into the query text, `end` is exclusive
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The span from the start of this one to the end of the other
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Token {
     kind: TokenType,
     lexemme: Option<String>,
+    span: Span,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Copy, Clone)]
 enum TokenType {
     // Keywords
     Select,
+    Describe,
+    Distinct,
     From,
     Where,
+    Group,
+    Having,
+    Order,
+    By,
+    Asc,
+    Desc,
+    Limit,
+    Offset,
+    As,
 
     Comma,
+    Star,
+    LeftParen,
+    RightParen,
     Identifier,
 
     Number,
     String,
+    Null,
+
+    // Arithmetic, Star is also multiplication
+    Plus,
+    Minus,
+    Slash,
+    Percent,
 
     // Operators
 
@@ -39,13 +177,28 @@ enum TokenType {
     GT,
     GEQ,
     EQ,
+    NEQ,
 
     // string
     Is,
+    Like,
+    ILike,
+    Matches,
+
+    // membership
+    In,
+    Between,
 
     // logical
     And,
     Or,
+    Not,
+}
+
+impl ParsedQuery {
+    pub fn is_grouped(&self) -> bool {
+        !self.group_by.is_empty() || !self.aggregates.is_empty()
+    }
 }
 
 impl Display for TokenType {
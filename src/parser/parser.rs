@@ -1,197 +1,905 @@
-use crate::{
-    data::DataAccessor,
-    filter::{FilterRule, LogicalFilter, LogicalOp, NumberFilter, NumberOp, StringFilter},
-    QueryError,
-};
-use std::iter::Peekable;
+use crate::{aggregate::AggregateFunc, value::Value, QueryError};
 
-use super::{lexer::Lexer, ParsedQuery, Token, TokenType};
+use super::{
+    ast::{BinaryOp, ColumnRef, Expr, Literal, OrderItem, Select, SelectItem},
+    diagnostic::bad_syntax,
+    lexer::Lexer,
+    Span, Statement, Token, TokenType,
+};
 
 const STRANGE_MISSING_LEXEMME_ERR: QueryError =
-    QueryError::BadSyntax("?? How did this token not have a lexemme?? This should never happen!");
+    QueryError::QueryFailed("?? How did this token not have a lexemme?? This should never happen!");
+
+/// The keywords that start a clause, the parser skips ahead to one of these after a syntax error
+const CLAUSE_KEYWORDS: [TokenType; 6] = [
+    TokenType::From,
+    TokenType::Where,
+    TokenType::Group,
+    TokenType::Having,
+    TokenType::Order,
+    TokenType::Limit,
+];
+
+/// The tokens that can follow the value on the left of a predicate
+const PREDICATE_OPERATORS: [TokenType; 13] = [
+    TokenType::LT,
+    TokenType::LEQ,
+    TokenType::GT,
+    TokenType::GEQ,
+    TokenType::EQ,
+    TokenType::NEQ,
+    TokenType::Is,
+    TokenType::Not,
+    TokenType::In,
+    TokenType::Between,
+    TokenType::Like,
+    TokenType::ILike,
+    TokenType::Matches,
+];
 
-const UNKNOWN_COL: QueryError = QueryError::BadSyntax("Unknown column for dataset");
+/// Turns query text into a syntax tree, without looking at any dataset.
+/// Names are resolved and types checked afterwards, by the `Analyzer`
 pub struct Parser<'a> {
-    lexer: Peekable<Lexer<'a>>,
+    /// Every token of the query, lexed up front so recovering from an error can step back to a clause keyword
+    tokens: Vec<Result<Token, QueryError>>,
+    /// Index of the next token
+    pos: usize,
+    source: &'a str,
+    /// Where the last token taken ended, so nodes know where their span ends
+    prev_end: usize,
+    errors: Vec<QueryError>,
 }
 
 // Public interface
 impl<'a> Parser<'a> {
-    pub fn parse(&mut self) -> Result<ParsedQuery, QueryError> {
-        let col_names = self.parse_select()?;
-        let from = self.parse_from()?;
-        let headers = DataAccessor::get_headers(&from)?;
-        let cols: Option<Vec<usize>> = col_names
-            .iter()
-            .map(|col| headers.iter().position(|x| x == col))
-            .collect();
-        let cols = cols.ok_or(UNKNOWN_COL)?;
-
-        let filter = self.parse_where(headers)?;
-        Ok(ParsedQuery { cols, from, filter })
+    /// Parses the whole query, recovering from syntax errors so that every one of them is reported
+    ///
+    /// The statement is only None if a clause the rest of the query depends on failed to parse.
+    /// Otherwise clauses that failed are left out of it, so it should only be used for finding more errors
+    /// unless there were none
+    pub fn parse(&mut self) -> (Option<Statement>, Vec<QueryError>) {
+        let statement = if self.peek_next_type(TokenType::Describe) {
+            self.recover(Parser::parse_describe)
+        } else {
+            self.parse_query()
+                .map(|select| Statement::Select(Box::new(select)))
+        };
+        if self.pos < self.tokens.len() {
+            let err = match self.get_next() {
+                Ok(extra) => self.unexpected("Expected the end of the query", &extra),
+                Err(e) => e,
+            };
+            self.errors.push(err);
+        }
+        (statement, std::mem::take(&mut self.errors))
     }
 
     pub fn new<'b>(input_query: &'b str) -> Parser<'b> {
         Parser {
-            lexer: Lexer::new(input_query).peekable(),
+            tokens: Lexer::new(input_query).collect(),
+            pos: 0,
+            source: input_query,
+            prev_end: 0,
+            errors: Vec::new(),
         }
     }
 }
 
+// Error recovery
+impl<'a> Parser<'a> {
+    /// Runs the parser for one clause. If it fails the error is recorded and the parser skips to the next clause
+    fn recover<T>(
+        &mut self,
+        parse_clause: fn(&mut Parser<'a>) -> Result<T, QueryError>,
+    ) -> Option<T> {
+        match parse_clause(self) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                self.synchronize(&e);
+                self.errors.push(e);
+                None
+            }
+        }
+    }
+
+    /// Skips tokens until the next clause keyword, reporting any lexer errors on the way
+    fn synchronize(&mut self, err: &QueryError) {
+        // An error about a clause keyword means the keyword came too early, like the from in `select from test`,
+        // so the next clause starts at that keyword
+        if let (Some(Ok(prev)), QueryError::BadSyntax(diagnostic)) =
+            (self.tokens.get(self.pos.wrapping_sub(1)), err)
+        {
+            if CLAUSE_KEYWORDS.contains(&prev.kind) && prev.span == diagnostic.span {
+                self.pos -= 1;
+                return;
+            }
+        }
+        self.skip_to_clause();
+    }
+
+    fn skip_to_clause(&mut self) {
+        while let Some(token) = self.tokens.get(self.pos) {
+            match token {
+                Ok(token) if CLAUSE_KEYWORDS.contains(&token.kind) => return,
+                Ok(_) => {}
+                Err(e) => self.errors.push(e.clone()),
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Reports a token that can't start any of the clauses that may still follow, like the `wher` in
+    /// `select a from t wher a > 1 order by a`, then skips to the next clause keyword so the clauses after it are still checked
+    fn skip_stray_tokens(&mut self, following: &[TokenType]) {
+        let stray = match self.tokens.get(self.pos) {
+            Some(Ok(token)) if following.contains(&token.kind) => return,
+            Some(Ok(token)) => token.clone(),
+            Some(Err(_)) => return self.skip_to_clause(),
+            None => return,
+        };
+        let mut expected: Vec<&str> = following
+            .iter()
+            .map(|kind| match kind {
+                TokenType::Where => "'where'",
+                TokenType::Group => "'group by'",
+                TokenType::Having => "'having'",
+                TokenType::Order => "'order by'",
+                _ => "'limit'",
+            })
+            .collect();
+        expected.push("the end of the query");
+        let last = expected.pop().unwrap_or_default();
+        let expected = if expected.is_empty() {
+            last.to_string()
+        } else {
+            format!("{} or {}", expected.join(", "), last)
+        };
+        let err = self.unexpected(&format!("Expected {}", expected), &stray);
+        self.errors.push(err);
+        self.pos += 1;
+        self.skip_to_clause();
+    }
+}
+
 // Query parsing
 impl<'a> Parser<'a> {
+    fn parse_describe(&mut self) -> Result<Statement, QueryError> {
+        self.get_next()?; // describe token
+        let dataset = self.match_next(
+            TokenType::Identifier,
+            "Expected dataset name after describe",
+        )?;
+        let span = dataset.span;
+        let dataset = dataset.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?;
+        Ok(Statement::Describe { dataset, span })
+    }
+
+    /// Each clause is parsed on its own, so an error in one doesn't stop the rest from being checked
+    fn parse_query(&mut self) -> Option<Select> {
+        let select = self.recover(Parser::parse_select);
+        let mut from = self.recover(Parser::parse_from);
+        // Recovering from a bad select list, like `select a b from test`, can stop at the real from clause
+        if from.is_none() && self.peek_next_type(TokenType::From) {
+            from = self.recover(Parser::parse_from);
+        }
+        // Anything between the clauses is reported and skipped, so a stray word doesn't hide the errors after it
+        self.skip_stray_tokens(&CLAUSE_KEYWORDS[1..]);
+        let filter = self.recover(Parser::parse_where);
+        self.skip_stray_tokens(&CLAUSE_KEYWORDS[2..]);
+        let group_by = self.recover(Parser::parse_group_by);
+        self.skip_stray_tokens(&CLAUSE_KEYWORDS[3..]);
+        let having = self.recover(Parser::parse_having);
+        self.skip_stray_tokens(&CLAUSE_KEYWORDS[4..]);
+        let order_by = self.recover(Parser::parse_order_by);
+        self.skip_stray_tokens(&CLAUSE_KEYWORDS[5..]);
+        let limit = self.recover(Parser::parse_limit);
+        while self.pos < self.tokens.len() {
+            self.skip_stray_tokens(&[]);
+        }
+        // Resolving the rest of the query depends on these, but the other clauses can be left out
+        let ((items, distinct), (from, from_span), group_by) = (select?, from?, group_by?);
+        let (limit, offset) = limit.unwrap_or((None, 0));
+        Some(Select {
+            items,
+            distinct,
+            from,
+            from_span,
+            filter: filter.flatten(),
+            group_by,
+            having: having.flatten(),
+            order_by: order_by.unwrap_or_default(),
+            limit,
+            offset,
+        })
+    }
+
     fn get_next(&mut self) -> Result<Token, QueryError> {
-        self.lexer
-            .next()
-            .ok_or(QueryError::BadSyntax("EOF reached"))?
+        match self.tokens.get(self.pos).cloned() {
+            Some(token) => {
+                self.pos += 1;
+                let token = token?;
+                self.prev_end = token.span.end;
+                Ok(token)
+            }
+            None => Err(bad_syntax("Unexpected end of query", self.end_span())),
+        }
     }
 
     /// Matches the next token with the given token type
     ///
-    /// Returns Err(QueryError::BadSyntax("EOF reached")) if there is no next token
+    /// Returns a BadSyntax error at the end of the query if there is no next token
     ///
-    /// Return Err(QueryError::BadSyntax(err)) if the token type doesn't match
+    /// Returns a BadSyntax error with `err` and the token's text if the token type doesn't match
     ///
     /// Returns Ok(token) otherwise
-    fn match_next(
-        &mut self,
-        token_type: TokenType,
-        err: &'static str,
-    ) -> Result<Token, QueryError> {
-        let next_token = self.get_next()?;
+    fn match_next(&mut self, token_type: TokenType, err: &str) -> Result<Token, QueryError> {
+        let next_token = self.match_any(err)?;
         if next_token.kind == token_type {
             Ok(next_token)
         } else {
-            Err(QueryError::BadSyntax(err))
+            Err(self.unexpected(err, &next_token))
+        }
+    }
+
+    /// Takes the next token whatever it is, with a BadSyntax error using `err` if the query has ended
+    fn match_any(&mut self, err: &str) -> Result<Token, QueryError> {
+        if self.pos >= self.tokens.len() {
+            return Err(bad_syntax(
+                format!("{}, found the end of the query", err),
+                self.end_span(),
+            ));
         }
+        self.get_next()
     }
 
     /// Peeks the next token, returns true if there is a next token and it is the correct type
-    fn peek_next_type(&mut self, token_type: TokenType) -> bool {
-        let peek = self.lexer.peek();
+    fn peek_next_type(&self, token_type: TokenType) -> bool {
+        let peek = self.tokens.get(self.pos);
         matches!(peek, Some(Ok(x)) if x.kind == token_type)
     }
 
-    fn parse_select(&mut self) -> Result<Vec<String>, QueryError> {
+    /// A syntax error at the token, quoting the token as it was written
+    fn unexpected(&self, err: &str, token: &Token) -> QueryError {
+        let text = &self.source[token.span.start..token.span.end];
+        bad_syntax(format!("{}, found '{}'", err, text), token.span)
+    }
+
+    /// Just past the last character of the query, ignoring trailing whitespace like the newline the repl reads
+    fn end_span(&self) -> Span {
+        let end = self.source.trim_end().len();
+        Span { start: end, end }
+    }
+
+    /// The span from the start of `start` to the end of the last token taken
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            start: start.start,
+            end: self.prev_end,
+        }
+    }
+
+    /// Returns the select list, and whether it was marked `distinct`
+    fn parse_select(&mut self) -> Result<(Vec<SelectItem>, bool), QueryError> {
         self.match_next(TokenType::Select, "Missing 'select'")?;
-        let first_col = self.match_next(
-            TokenType::Identifier,
-            "Expected at least one column after select",
-        )?;
+        let distinct = self.peek_next_type(TokenType::Distinct);
+        if distinct {
+            self.get_next()?;
+        }
         let mut cols = Vec::new();
-        cols.push(first_col.lexemme.unwrap());
+        cols.push(self.parse_select_item("Expected at least one column after select")?);
 
         while self.peek_next_type(TokenType::Comma) {
-            self.lexer.next();
-            let next_col = self.match_next(
-                TokenType::Identifier,
-                "Expected column identifier after comma in select",
-            )?;
-            cols.push(next_col.lexemme.unwrap());
+            self.get_next()?;
+            cols.push(self.parse_select_item("Expected column identifier after comma in select")?);
+        }
+        Ok((cols, distinct))
+    }
+
+    /// A single entry in the select list: a `*` or `<dataset>.*` wildcard, or an expression with an optional alias
+    fn parse_select_item(&mut self, err: &str) -> Result<SelectItem, QueryError> {
+        let (dataset, span) = match self.tokens.get(self.pos) {
+            Some(Ok(token)) if token.kind == TokenType::Star => (None, token.span),
+            Some(Ok(Token {
+                kind: TokenType::Identifier,
+                lexemme: Some(name),
+                span,
+            })) if name.ends_with(".*") => (Some(name.trim_end_matches(".*").to_string()), *span),
+            _ => {
+                let expr = self.parse_sum(err)?;
+                let alias = if self.peek_next_type(TokenType::As) {
+                    self.get_next()?;
+                    let alias =
+                        self.match_next(TokenType::Identifier, "Expected a name after 'as'")?;
+                    Some(ColumnRef {
+                        name: alias.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?,
+                        span: alias.span,
+                    })
+                } else {
+                    None
+                };
+                return Ok(SelectItem::Expr { expr, alias });
+            }
+        };
+        self.get_next()?;
+        Ok(SelectItem::Wildcard { dataset, span })
+    }
+
+    /// A column name or an aggregate call like `avg(col)`, given its first token
+    fn parse_column(&mut self, col: Token, err: &str) -> Result<Expr, QueryError> {
+        if col.kind != TokenType::Identifier {
+            return Err(self.unexpected(err, &col));
+        }
+        let span = col.span;
+        let name = col.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?;
+        if self.peek_next_type(TokenType::LeftParen) {
+            self.parse_aggregate(&name, span)
+        } else {
+            Ok(Expr::Column(ColumnRef { name, span }))
+        }
+    }
+
+    /// Parses the `(col)`, `(distinct col)` or `(*)` following the name of an aggregate function
+    fn parse_aggregate(&mut self, func_name: &str, name_span: Span) -> Result<Expr, QueryError> {
+        let func = AggregateFunc::from_name(func_name).ok_or_else(|| {
+            bad_syntax(
+                format!(
+                    "Unknown aggregate '{}', expected one of count, sum, avg, min or max",
+                    func_name
+                ),
+                name_span,
+            )
+        })?;
+        self.match_next(TokenType::LeftParen, "Expected '(' after aggregate name")?;
+        let distinct = self.peek_next_type(TokenType::Distinct);
+        if distinct {
+            self.get_next()?;
         }
-        Ok(cols)
+        let arg = self.get_next()?;
+        let col = match arg.kind {
+            TokenType::Star if distinct => {
+                return Err(bad_syntax(
+                    "Distinct aggregates need a column, not '*'",
+                    arg.span,
+                ))
+            }
+            TokenType::Star if func == AggregateFunc::Count => None,
+            TokenType::Star => {
+                return Err(bad_syntax(
+                    format!(
+                        "Only count can be used with '*' as its argument, not {}",
+                        func.name()
+                    ),
+                    arg.span,
+                ))
+            }
+            TokenType::Identifier => Some(ColumnRef {
+                name: arg.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?,
+                span: arg.span,
+            }),
+            _ => {
+                return Err(self.unexpected(
+                    "Expected a column name or '*' as the aggregate argument",
+                    &arg,
+                ))
+            }
+        };
+        self.match_next(
+            TokenType::RightParen,
+            "Expected ')' after aggregate argument",
+        )?;
+        Ok(Expr::Aggregate {
+            func,
+            distinct,
+            col,
+            span: self.span_from(name_span),
+        })
     }
 
-    fn parse_from(&mut self) -> Result<String, QueryError> {
+    fn parse_from(&mut self) -> Result<(String, Span), QueryError> {
         self.match_next(TokenType::From, "Missing 'from'")?;
         let from = self.match_next(TokenType::Identifier, "Expected dataset name after from")?;
         let db_name = from.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?;
-        Ok(db_name)
+        Ok((db_name, from.span))
     }
 
-    fn parse_where(
-        &mut self,
-        headers: Vec<String>,
-    ) -> Result<Option<Box<dyn FilterRule>>, QueryError> {
+    fn parse_where(&mut self) -> Result<Option<Expr>, QueryError> {
         if !self.peek_next_type(TokenType::Where) {
             Ok(None)
         } else {
-            self.lexer.next(); // where token
-            let filter = self.parse_filter(headers)?;
+            self.get_next()?; // where token
+            let filter = self.parse_filter()?;
             Ok(Some(filter))
         }
     }
 }
 
+// Group by parsing
+impl<'a> Parser<'a> {
+    fn parse_group_by(&mut self) -> Result<Vec<ColumnRef>, QueryError> {
+        let mut group_by = Vec::new();
+        if !self.peek_next_type(TokenType::Group) {
+            return Ok(group_by);
+        }
+        self.get_next()?; // group token
+        self.match_next(TokenType::By, "Expected 'by' after 'group'")?;
+        loop {
+            let col_token = self.match_next(
+                TokenType::Identifier,
+                "Expected column name in group by clause",
+            )?;
+            group_by.push(ColumnRef {
+                name: col_token.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?,
+                span: col_token.span,
+            });
+            if !self.peek_next_type(TokenType::Comma) {
+                return Ok(group_by);
+            }
+            self.get_next()?;
+        }
+    }
+}
+
+// Having parsing
+impl<'a> Parser<'a> {
+    /// The having clause is a filter over the aggregated records, so it can refer to group by columns and aggregates
+    fn parse_having(&mut self) -> Result<Option<Expr>, QueryError> {
+        if !self.peek_next_type(TokenType::Having) {
+            return Ok(None);
+        }
+        self.get_next()?; // having token
+        let filter = self.parse_filter()?;
+        Ok(Some(filter))
+    }
+}
+
+// Order by parsing
+impl<'a> Parser<'a> {
+    fn parse_order_by(&mut self) -> Result<Vec<OrderItem>, QueryError> {
+        let mut keys = Vec::new();
+        if !self.peek_next_type(TokenType::Order) {
+            return Ok(keys);
+        }
+        self.get_next()?; // order token
+        self.match_next(TokenType::By, "Expected 'by' after 'order'")?;
+        keys.push(self.parse_order_key()?);
+        while self.peek_next_type(TokenType::Comma) {
+            self.get_next()?;
+            keys.push(self.parse_order_key()?);
+        }
+        Ok(keys)
+    }
+
+    /// An expression optionally followed by `asc` or `desc`, ascending if neither is given
+    fn parse_order_key(&mut self) -> Result<OrderItem, QueryError> {
+        let expr = self.parse_sum("Expected column name in order by clause")?;
+        let descending = if self.peek_next_type(TokenType::Desc) {
+            self.get_next()?;
+            true
+        } else {
+            if self.peek_next_type(TokenType::Asc) {
+                self.get_next()?;
+            }
+            false
+        };
+        Ok(OrderItem { expr, descending })
+    }
+}
+
+// Limit parsing
+impl<'a> Parser<'a> {
+    /// Parses `limit N [offset M]`, returning no limit and an offset of 0 if the clause is missing
+    fn parse_limit(&mut self) -> Result<(Option<usize>, usize), QueryError> {
+        if !self.peek_next_type(TokenType::Limit) {
+            return Ok((None, 0));
+        }
+        self.get_next()?; // limit token
+        let limit = self.parse_count("Expected a number of rows after 'limit'")?;
+        let offset = if self.peek_next_type(TokenType::Offset) {
+            self.get_next()?;
+            self.parse_count("Expected a number of rows after 'offset'")?
+        } else {
+            0
+        };
+        Ok((Some(limit), offset))
+    }
+
+    fn parse_count(&mut self, err: &str) -> Result<usize, QueryError> {
+        let num_token = self.match_next(TokenType::Number, err)?;
+        let span = num_token.span;
+        let num_lexemme = num_token.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?;
+        num_lexemme.parse::<usize>().map_err(|_| {
+            bad_syntax(
+                format!("Expected a whole number, found '{}'", num_lexemme),
+                span,
+            )
+        })
+    }
+}
+
 // Filter parsing
 impl<'a> Parser<'a> {
-    fn parse_filter(&mut self, headers: Vec<String>) -> Result<Box<dyn FilterRule>, QueryError> {
-        let col_token = self.match_next(
-            TokenType::Identifier,
-            "Expected col name as first token in filter",
+    /// Parses a boolean expression of predicates over values, from loosest to tightest binding:
+    ///
+    /// ```text
+    /// filter    := and_chain ('or' and_chain)*
+    /// and_chain := unary ('and' unary)*
+    /// unary     := 'not' unary | predicate
+    /// predicate := sum (compare_op sum | 'is' ['not'] 'null' | ['not'] (in | between | like | matches))
+    ///            | '(' filter ')'
+    /// sum       := term (('+' | '-') term)*
+    /// term      := factor (('*' | '/' | '%') factor)*
+    /// factor    := '-' factor | number | string | column | aggregate | '(' filter ')'
+    /// ```
+    ///
+    /// Chains of the same operator nest to the left, so `f1 and f2 and f3` is ((f1, f2), f3).
+    /// Parentheses hold a whole filter so they can group either predicates or arithmetic,
+    /// the analyzer checks that each ends up where its type belongs
+    fn parse_filter(&mut self) -> Result<Expr, QueryError> {
+        let mut filter = self.parse_and_chain()?;
+        while self.peek_next_type(TokenType::Or) {
+            self.get_next()?;
+            let right = self.parse_and_chain()?;
+            filter = Expr::Binary {
+                left: Box::new(filter),
+                op: BinaryOp::Or,
+                right: Box::new(right),
+            };
+        }
+        Ok(filter)
+    }
+
+    fn parse_and_chain(&mut self) -> Result<Expr, QueryError> {
+        let mut filter = self.parse_unary_filter()?;
+        while self.peek_next_type(TokenType::And) {
+            self.get_next()?;
+            let right = self.parse_unary_filter()?;
+            filter = Expr::Binary {
+                left: Box::new(filter),
+                op: BinaryOp::And,
+                right: Box::new(right),
+            };
+        }
+        Ok(filter)
+    }
+
+    fn parse_unary_filter(&mut self) -> Result<Expr, QueryError> {
+        if self.peek_next_type(TokenType::Not) {
+            self.get_next()?;
+            let f = self.parse_unary_filter()?;
+            Ok(Expr::Not(Box::new(f)))
+        } else {
+            self.parse_predicate()
+        }
+    }
+
+    /// A single comparison of a value against another, or a parenthesized filter
+    fn parse_predicate(&mut self) -> Result<Expr, QueryError> {
+        let expr = self.parse_sum("Expected a column or value to filter on")?;
+        let next = match self.tokens.get(self.pos) {
+            Some(Ok(token)) => Some(token.kind),
+            _ => None,
+        };
+        let is_operator = matches!(next, Some(kind) if PREDICATE_OPERATORS.contains(&kind));
+        // A value on its own before a ')' is parenthesized arithmetic, like `(avg + 5) * 2`
+        if !is_operator && (expr.is_condition() || next == Some(TokenType::RightParen)) {
+            return Ok(expr);
+        }
+        let filter_kind = self.match_any(
+            "Expected a comparison operator, 'is', 'in', 'between', 'like', 'ilike' or 'matches'",
         )?;
-        let col_name = col_token.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?;
-        let col = headers
-            .iter()
-            .position(|x| &col_name == x)
-            .ok_or(UNKNOWN_COL)?;
-        let filter_kind = self.get_next()?;
-        let filter_val = self.get_next()?;
-        // println!("{:?} | {:?} | {:?}", col, filter_kind, filter_val);
-        let filter_res: Result<Box<dyn FilterRule>, QueryError> = match filter_val.kind {
-            TokenType::Number => {
-                let num_lexemme = filter_val.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?;
-                let num = num_lexemme.parse::<usize>().map_err( |num_err| QueryError::NumParseError(num_err.to_string()))?;
-                let num_filter = NumberFilter {
-                    col,
-                    op: Parser::map_num_op(filter_kind.kind)?,
-                    val: num,
-                };
-                Ok(Box::new(num_filter))
+        // `not in`, `not between` and `not like` are the negations of the predicates without the `not`
+        let negated = filter_kind.kind == TokenType::Not;
+        let kind = if negated {
+            self.get_next()?
+        } else {
+            filter_kind
+        };
+        let predicate = match kind.kind {
+            TokenType::In => self.parse_in_list(expr)?,
+            TokenType::Between => self.parse_between(expr)?,
+            TokenType::Like | TokenType::ILike | TokenType::Matches => {
+                self.parse_pattern(expr, kind.kind)?
             }
-            TokenType::String => {
-                if filter_kind.kind != TokenType::Is {
-                    Err(QueryError::BadSyntax("Operator for string comparisons must be 'is'"))
-                } else {
-                    let val = filter_val.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?;
-                    let str_filter = StringFilter {
-                        col,
-                        val,
-                    };
-                    Ok(Box::new(str_filter))
-                }
+            _ if negated => {
+                return Err(self.unexpected(
+                    "Expected 'in', 'between', 'like', 'ilike' or 'matches' after 'not'",
+                    &kind,
+                ))
             }
-            _ => Err(QueryError::BadSyntax("Invalid token type for a filter value, must be a string or a number and not a keyword"))
+            _ => return self.parse_comparison(expr, kind),
         };
-        let filter = filter_res?;
-
-        // Check if this is the first filter of a logical op
-        // Note: The parsed filter structure of `f1 AND f2 AND f3 AND f4 ...` will be (f1, (f2, (f3, (...))))
-        // Allows for short circuiting on the left filter before recursing in to the right filter
-        let is_logical = self.peek_next_type(TokenType::And) || self.peek_next_type(TokenType::Or);
-        if is_logical {
-            let logical_kind = self.get_next()?;
-            let f2 = self.parse_filter(headers)?;
-            let logical_filter = LogicalFilter {
-                f1: filter,
-                f2,
-                op: Parser::map_logic_op(logical_kind.kind)?,
-            };
-            Ok(Box::new(logical_filter))
+        if negated {
+            Ok(Expr::Not(Box::new(predicate)))
         } else {
-            // If no logical op, then just return the filter as is
-            Ok(filter)
+            Ok(predicate)
+        }
+    }
+
+    /// Parses a comparison operator or `is [not]`, and the value after it
+    fn parse_comparison(&mut self, expr: Expr, op: Token) -> Result<Expr, QueryError> {
+        // `is not` is the negated form of `is`
+        let negated = op.kind == TokenType::Is && self.peek_next_type(TokenType::Not);
+        if negated {
+            self.get_next()?;
+        }
+        let compare_op = match op.kind {
+            TokenType::Is if negated => BinaryOp::NEQ,
+            TokenType::Is | TokenType::EQ => BinaryOp::EQ,
+            TokenType::NEQ => BinaryOp::NEQ,
+            TokenType::LT => BinaryOp::LT,
+            TokenType::GT => BinaryOp::GT,
+            TokenType::LEQ => BinaryOp::LEQ,
+            TokenType::GEQ => BinaryOp::GEQ,
+            _ => {
+                return Err(self.unexpected(
+                    "Expected a comparison operator, 'is', 'in', 'between', 'like', 'ilike' or 'matches'",
+                    &op,
+                ))
+            }
+        };
+        if self.peek_next_type(TokenType::Null) {
+            let null = self.get_next()?;
+            if op.kind != TokenType::Is {
+                return Err(bad_syntax(
+                    "Use 'is null' or 'is not null' to check for NULL, comparing against NULL is never true",
+                    op.span.to(null.span),
+                ));
+            }
+            return Ok(Expr::IsNull {
+                span: self.span_from(expr.span()),
+                expr: Box::new(expr),
+                negated,
+            });
+        }
+        let right = self.parse_sum("Expected a value to compare against")?;
+        if let (TokenType::Is, Expr::Literal { val, .. }) = (op.kind, &right) {
+            if val.is_number() {
+                return Err(bad_syntax(
+                    "Invalid operator for number comparisons, use '==' or '!=' instead of 'is'",
+                    op.span,
+                ));
+            }
+        }
+        Ok(Expr::Binary {
+            left: Box::new(expr),
+            op: compare_op,
+            right: Box::new(right),
+        })
+    }
+
+    /// Parses the `(val, val, ...)` after `in`
+    fn parse_in_list(&mut self, expr: Expr) -> Result<Expr, QueryError> {
+        self.match_next(TokenType::LeftParen, "Expected '(' after 'in'")?;
+        let mut list = Vec::new();
+        loop {
+            let val = self.get_next()?;
+            match val.kind {
+                TokenType::String => {
+                    list.push(Literal::String(
+                        val.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?,
+                    ));
+                }
+                TokenType::Number | TokenType::Minus => {
+                    list.push(self.parse_number(val)?);
+                }
+                _ => {
+                    return Err(
+                        self.unexpected("Values in an in list must be strings or numbers", &val)
+                    )
+                }
+            }
+            if !self.peek_next_type(TokenType::Comma) {
+                break;
+            }
+            self.get_next()?;
         }
+        self.match_next(TokenType::RightParen, "Expected ')' to close the in list")?;
+        Ok(Expr::In {
+            span: self.span_from(expr.span()),
+            expr: Box::new(expr),
+            list,
+        })
     }
 
-    fn map_num_op(kind: TokenType) -> Result<NumberOp, QueryError> {
+    /// Parses the pattern string after `like`, `ilike` or `matches`
+    fn parse_pattern(&mut self, expr: Expr, kind: TokenType) -> Result<Expr, QueryError> {
+        let pattern = self.match_next(TokenType::String, "Expected a pattern string")?;
+        let pattern_span = pattern.span;
+        let pattern = pattern.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?;
+        let span = self.span_from(expr.span());
+        let expr = Box::new(expr);
         match kind {
-            TokenType::LT => Ok(NumberOp::LT),
-            TokenType::GT => Ok(NumberOp::GT),
-            TokenType::LEQ => Ok(NumberOp::LEQ),
-            TokenType::GEQ => Ok(NumberOp::GEQ),
-            TokenType::EQ => Ok(NumberOp::EQ),
-            _ => Err(QueryError::BadSyntax(
-                "Invalid operator for number comparisons",
+            TokenType::Like | TokenType::ILike => Ok(Expr::Like {
+                expr,
+                pattern,
+                case_insensitive: kind == TokenType::ILike,
+                span,
+            }),
+            _ => Ok(Expr::Matches {
+                expr,
+                pattern,
+                pattern_span,
+                span,
+            }),
+        }
+    }
+
+    /// Parses the `low and high` after `between`
+    fn parse_between(&mut self, expr: Expr) -> Result<Expr, QueryError> {
+        let low = self.get_next()?;
+        let low = self.parse_number(low)?;
+        self.match_next(
+            TokenType::And,
+            "Expected 'and' between the bounds of 'between'",
+        )?;
+        let high = self.get_next()?;
+        let high = self.parse_number(high)?;
+        Ok(Expr::Between {
+            span: self.span_from(expr.span()),
+            expr: Box::new(expr),
+            low,
+            high,
+        })
+    }
+
+    /// Parses additions and subtractions of terms
+    fn parse_sum(&mut self, err: &str) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_term(err)?;
+        while let Some(op) = self.match_operator(&[
+            (TokenType::Plus, BinaryOp::Add),
+            (TokenType::Minus, BinaryOp::Sub),
+        ]) {
+            let right = self.parse_term(&format!("Expected a value after '{}'", op))?;
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                op,
+                right: Box::new(right),
+            };
+        }
+        Ok(expr)
+    }
+
+    /// Parses multiplications, divisions and remainders of factors
+    fn parse_term(&mut self, err: &str) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_factor(err)?;
+        while let Some(op) = self.match_operator(&[
+            (TokenType::Star, BinaryOp::Mul),
+            (TokenType::Slash, BinaryOp::Div),
+            (TokenType::Percent, BinaryOp::Mod),
+        ]) {
+            let right = self.parse_factor(&format!("Expected a value after '{}'", op))?;
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                op,
+                right: Box::new(right),
+            };
+        }
+        Ok(expr)
+    }
+
+    /// Parses a single value, a negated one or a parenthesized expression
+    fn parse_factor(&mut self, err: &str) -> Result<Expr, QueryError> {
+        let token = self.match_any(err)?;
+        let span = token.span;
+        match token.kind {
+            TokenType::Minus if !self.peek_next_type(TokenType::Number) => {
+                let expr = self.parse_factor("Expected a value after '-'")?;
+                Ok(Expr::Negate {
+                    expr: Box::new(expr),
+                    span: self.span_from(span),
+                })
+            }
+            TokenType::Number | TokenType::Minus => {
+                let val = self.parse_number(token)?;
+                Ok(Expr::Literal {
+                    val,
+                    span: self.span_from(span),
+                })
+            }
+            TokenType::String => Ok(Expr::Literal {
+                val: Literal::String(token.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?),
+                span,
+            }),
+            TokenType::Identifier => self.parse_column(token, err),
+            TokenType::LeftParen => {
+                let expr = self.parse_filter()?;
+                self.match_next(
+                    TokenType::RightParen,
+                    "Expected ')' to close the parentheses",
+                )?;
+                Ok(expr)
+            }
+            _ => Err(self.unexpected(err, &token)),
+        }
+    }
+
+    /// Takes the next token if it is one of the operators, returning what it stands for
+    fn match_operator(&mut self, ops: &[(TokenType, BinaryOp)]) -> Option<BinaryOp> {
+        let (_, op) = ops.iter().find(|(kind, _)| self.peek_next_type(*kind))?;
+        self.get_next().ok()?;
+        Some(*op)
+    }
+
+    /// Parses a number literal, given either its token or the '-' before it
+    fn parse_number(&mut self, first: Token) -> Result<Literal, QueryError> {
+        let start = first.span;
+        let (sign, num_token) = match first.kind {
+            TokenType::Minus => (
+                "-",
+                self.match_next(TokenType::Number, "Expected a number after '-'")?,
+            ),
+            TokenType::Number => ("", first),
+            _ => return Err(self.unexpected("Expected a number", &first)),
+        };
+        let span = start.to(num_token.span);
+        let num_lexemme = num_token.lexemme.ok_or(STRANGE_MISSING_LEXEMME_ERR)?;
+        let literal = format!("{}{}", sign, num_lexemme);
+        match Value::parse_number(&literal) {
+            Some(Value::Int(i)) => Ok(Literal::Int(i)),
+            Some(Value::Float(f)) => Ok(Literal::Float(f)),
+            _ => Err(bad_syntax(
+                format!("'{}' is not a valid number", literal),
+                span,
             )),
         }
     }
+}
 
-    fn map_logic_op(kind: TokenType) -> Result<LogicalOp, QueryError> {
-        match kind {
-            TokenType::And => Ok(LogicalOp::And),
-            TokenType::Or => Ok(LogicalOp::Or),
-            _ => Err(QueryError::BadSyntax("?? How did I get an invalid logical op type, didn't I just check this just before?"))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_messages(query: &str) -> Vec<String> {
+        let (_, errors) = Parser::new(query).parse();
+        errors
+            .into_iter()
+            .map(|e| match e {
+                QueryError::BadSyntax(diagnostic) | QueryError::BadLex(diagnostic) => {
+                    diagnostic.message
+                }
+                e => panic!("'{}' gave an error without a location: {:?}", query, e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stray_tokens_between_clauses_are_skipped() {
+        let errors = error_messages("select code, from test wher avg > 3 order avg");
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[1].ends_with("found 'wher'"));
+        assert_eq!(errors[2], "Expected 'by' after 'order', found 'avg'");
+    }
+
+    #[test]
+    fn clauses_out_of_order_are_reported() {
+        let errors = error_messages("select code from test order by code where avg > 1 limit 2");
+        assert_eq!(
+            errors,
+            ["Expected 'limit' or the end of the query, found 'where'"]
+        );
+    }
+
+    #[test]
+    fn from_is_found_after_a_bad_select_list() {
+        let errors = error_messages("select a b from test where");
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(errors[0], "Missing 'from', found 'b'");
+    }
+
+    #[test]
+    fn bad_counts_point_at_themselves() {
+        let errors = error_messages("select code, from test limit 1.5 offset 2");
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(errors[1], "Expected a whole number, found '1.5'");
+    }
+
+    #[test]
+    fn end_of_query_ignores_trailing_whitespace() {
+        let query = "select code from test where\n";
+        let (_, errors) = Parser::new(query).parse();
+        match &errors[..] {
+            [QueryError::BadSyntax(diagnostic)] => assert_eq!(diagnostic.span.start, 27),
+            errors => panic!("{:?}", errors),
         }
     }
 }
@@ -0,0 +1,455 @@
+//! Prints statements back out as canonical query text: lowercase keywords, single spaces,
+//! double quoted strings and only the parentheses the tree needs
+//!
+//! Parsing the printed text of a statement the parser produced gives back the same tree, see `Statement::same_tree`
+
+use std::fmt::{Display, Formatter, Result};
+
+use super::{
+    ast::{BinaryOp, Expr, Literal, OrderItem, Select, SelectItem},
+    Statement,
+};
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Statement::Select(select) => write!(f, "{}", select),
+            Statement::Describe { dataset, .. } => write!(f, "describe {}", dataset),
+        }
+    }
+}
+
+impl Display for Select {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "select ")?;
+        if self.distinct {
+            write!(f, "distinct ")?;
+        }
+        write_list(f, &self.items)?;
+        write!(f, " from {}", self.from)?;
+        if let Some(filter) = &self.filter {
+            write!(f, " where {}", filter)?;
+        }
+        if !self.group_by.is_empty() {
+            let names: Vec<&str> = self.group_by.iter().map(|x| x.name.as_str()).collect();
+            write!(f, " group by {}", names.join(", "))?;
+        }
+        if let Some(having) = &self.having {
+            write!(f, " having {}", having)?;
+        }
+        if !self.order_by.is_empty() {
+            write!(f, " order by ")?;
+            write_list(f, &self.order_by)?;
+        }
+        // An offset can only be written after a limit
+        if let Some(limit) = self.limit {
+            write!(f, " limit {}", limit)?;
+            if self.offset != 0 {
+                write!(f, " offset {}", self.offset)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_list<T: Display>(f: &mut Formatter<'_>, items: &[T]) -> Result {
+    for (i, item) in items.iter().enumerate() {
+        if i != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+impl Display for SelectItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            SelectItem::Expr {
+                expr,
+                alias: Some(alias),
+            } => write!(f, "{} as {}", expr, alias.name),
+            SelectItem::Expr { expr, .. } => write!(f, "{}", expr),
+            SelectItem::Wildcard { .. } => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+impl Display for OrderItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.expr)?;
+        if self.descending {
+            write!(f, " desc")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Expr::Column(_) | Expr::Aggregate { .. } => write!(f, "{}", self.name()),
+            Expr::Literal { val, .. } => write!(f, "{}", val),
+            // A minus sign right before a number is read as part of the number, so negated numbers keep their parentheses
+            Expr::Negate { expr, .. } => match expr.as_ref() {
+                Expr::Literal { val, .. } if val.is_number() => write!(f, "-({})", expr),
+                _ => {
+                    write!(f, "-")?;
+                    write_operand(f, expr, precedence(self))
+                }
+            },
+            Expr::Binary { left, op, right } => {
+                let own = precedence(self);
+                // Comparisons don't chain, so a comparison on the left needs parentheses too
+                let left_min = if op.is_comparison() { own + 1 } else { own };
+                write_operand(f, left, left_min)?;
+                write!(f, " {} ", op)?;
+                // Chains of the same operator nest to the left, so the right side needs parentheses if it binds the same
+                write_operand(f, right, own + 1)
+            }
+            // Negated predicates are written the way they would be in english, like `dept not in (...)`
+            Expr::Not(expr) => match expr.as_ref() {
+                Expr::In { .. }
+                | Expr::Between { .. }
+                | Expr::Like { .. }
+                | Expr::Matches { .. } => write_predicate(f, expr, true),
+                _ => {
+                    write!(f, "not ")?;
+                    write_operand(f, expr, precedence(self))
+                }
+            },
+            _ => write_predicate(f, self, false),
+        }
+    }
+}
+
+/// How tightly the expression binds, higher binds tighter
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Binary {
+            op: BinaryOp::Or, ..
+        } => 0,
+        Expr::Binary {
+            op: BinaryOp::And, ..
+        } => 1,
+        Expr::Not(_) => 2,
+        Expr::Binary {
+            op: BinaryOp::Add | BinaryOp::Sub,
+            ..
+        } => 4,
+        Expr::Binary {
+            op: BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod,
+            ..
+        } => 5,
+        // Comparisons and the other predicates
+        Expr::Binary { .. }
+        | Expr::IsNull { .. }
+        | Expr::In { .. }
+        | Expr::Between { .. }
+        | Expr::Like { .. }
+        | Expr::Matches { .. } => 3,
+        Expr::Negate { .. } => 6,
+        Expr::Column(_) | Expr::Aggregate { .. } | Expr::Literal { .. } => 7,
+    }
+}
+
+/// Writes the operand of an operator, parenthesized if it binds looser than `min_precedence`
+fn write_operand(f: &mut Formatter<'_>, operand: &Expr, min_precedence: u8) -> Result {
+    if precedence(operand) < min_precedence {
+        write!(f, "({})", operand)
+    } else {
+        write!(f, "{}", operand)
+    }
+}
+
+/// Writes `is null`, `in`, `between`, `like`, `ilike` or `matches`, with `not` after the value if negated
+fn write_predicate(f: &mut Formatter<'_>, predicate: &Expr, negated: bool) -> Result {
+    let not = if negated { "not " } else { "" };
+    let (expr, rest) = match predicate {
+        Expr::IsNull { expr, negated, .. } => {
+            let not = if *negated { "not " } else { "" };
+            (expr, format!("is {}null", not))
+        }
+        Expr::In { expr, list, .. } => {
+            let list: Vec<String> = list.iter().map(|val| val.to_string()).collect();
+            (expr, format!("{}in ({})", not, list.join(", ")))
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => (expr, format!("{}between {} and {}", not, low, high)),
+        Expr::Like {
+            expr,
+            pattern,
+            case_insensitive,
+            ..
+        } => (
+            expr,
+            format!(
+                "{}{} {}",
+                not,
+                if *case_insensitive { "ilike" } else { "like" },
+                string(pattern)
+            ),
+        ),
+        Expr::Matches { expr, pattern, .. } => {
+            (expr, format!("{}matches {}", not, string(pattern)))
+        }
+        _ => unreachable!("Only called with predicates"),
+    };
+    // The value on the left is parsed as arithmetic, anything looser needs parentheses
+    write_operand(f, expr, 4)?;
+    write!(f, " {}", rest)
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let op = match self {
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::LT => "<",
+            BinaryOp::LEQ => "<=",
+            BinaryOp::GT => ">",
+            BinaryOp::GEQ => ">=",
+            BinaryOp::EQ => "==",
+            BinaryOp::NEQ => "!=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Literal::Int(i) => write!(f, "{}", i),
+            // Written so they read back as floats, `65.0` rather than `65`
+            Literal::Float(x) => write!(f, "{:?}", x),
+            Literal::String(s) => write!(f, "{}", string(s)),
+        }
+    }
+}
+
+/// Quotes inside the string are doubled
+fn string(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        aggregate::AggregateFunc,
+        parse,
+        parser::{
+            ast::{BinaryOp, ColumnRef, Expr, Literal, OrderItem, Select, SelectItem},
+            Span, Statement,
+        },
+    };
+
+    /// Prints the statement, parses it back and checks it is the same tree and prints the same way again
+    fn assert_round_trip(statement: &Statement) {
+        let printed = statement.to_string();
+        let reparsed =
+            parse(&printed).unwrap_or_else(|e| panic!("'{}' failed to parse back: {}", printed, e));
+        assert!(
+            statement.same_tree(&reparsed),
+            "'{}' parsed back to a different tree:\n{:?}\n{:?}",
+            printed,
+            statement,
+            reparsed
+        );
+        assert_eq!(reparsed.to_string(), printed);
+    }
+
+    #[test]
+    fn queries_round_trip() {
+        let queries = [
+            "select * from test",
+            "select  distinct dept, test.* from test where avg>50 order by dept, avg desc limit 5 offset 2",
+            "select dept, count(*), count(distinct instructor), avg(avg) from test group by dept having count(*) > 3",
+            "select code from test where (dept is \"CPSC\" or dept is \"MATH\") and not avg < 50",
+            "select code from test where a > 1 and (b > 2 and c > 3) or not (d > 1 or e > 2)",
+            "select code from test where x not in (1, -2.5, 3e10) or y not between -1 and 2.0",
+            "select code from test where z not like \"a\"\"b\" or w not matches \"q\" or v ilike \"%x\"",
+            "select code from test where x is not null and not y is null and w is not \"a\"",
+            "select code, avg * 1.1 as curved from test where avg + 5 > 70 order by curved",
+            "select a - -3, a - (b - c), (a - b) - c, a / b * c, a / (b * c), -(a + 1) % 2 from t",
+            "select -(3), -(-3), -(-a), - - a, -(2.5) * a from t where (a + 1) * 2 > -(b)",
+            "select avg as average, count(*) as n from test group by avg having n > 1 order by n desc",
+            "describe test",
+        ];
+        for query in queries.iter() {
+            let statement = parse(query).unwrap_or_else(|e| panic!("'{}': {}", query, e));
+            assert_round_trip(&statement);
+        }
+    }
+
+    #[test]
+    fn negated_numbers_keep_their_parentheses() {
+        let statement = parse("select -(3), -3 from test").unwrap();
+        assert_eq!(statement.to_string(), "select -(3), -3 from test");
+    }
+
+    /// Xorshift, so the generated trees are the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn pick<T: Copy>(&mut self, options: &[T]) -> T {
+            options[self.below(options.len() as u64) as usize]
+        }
+    }
+
+    fn column(rng: &mut Rng) -> ColumnRef {
+        ColumnRef {
+            name: rng.pick(&["a", "b", "code", "avg_2"]).to_string(),
+            span: Span::default(),
+        }
+    }
+
+    fn number(rng: &mut Rng) -> Literal {
+        match rng.below(3) {
+            0 => Literal::Int(rng.below(200) as i64 - 100),
+            1 => Literal::Float((rng.below(2000) as f64 - 1000.0) / 8.0),
+            _ => Literal::Float(rng.pick(&[1e300, -2.5e-7, 0.1, -0.0])),
+        }
+    }
+
+    fn literal(val: Literal) -> Expr {
+        Expr::Literal {
+            val,
+            span: Span::default(),
+        }
+    }
+
+    fn value(rng: &mut Rng, depth: u32) -> Expr {
+        let choice = if depth == 0 {
+            rng.below(4)
+        } else {
+            rng.below(7)
+        };
+        match choice {
+            0 => Expr::Column(column(rng)),
+            1 => literal(number(rng)),
+            2 => literal(Literal::String(
+                rng.pick(&["", "x", "say \"hi\""]).to_string(),
+            )),
+            3 => Expr::Aggregate {
+                func: rng.pick(&[AggregateFunc::Count, AggregateFunc::Sum, AggregateFunc::Max]),
+                distinct: rng.below(2) == 0,
+                col: Some(column(rng)),
+                span: Span::default(),
+            },
+            4 => Expr::Negate {
+                expr: Box::new(value(rng, depth - 1)),
+                span: Span::default(),
+            },
+            _ => Expr::Binary {
+                left: Box::new(value(rng, depth - 1)),
+                op: rng.pick(&[
+                    BinaryOp::Add,
+                    BinaryOp::Sub,
+                    BinaryOp::Mul,
+                    BinaryOp::Div,
+                    BinaryOp::Mod,
+                ]),
+                right: Box::new(value(rng, depth - 1)),
+            },
+        }
+    }
+
+    fn condition(rng: &mut Rng, depth: u32) -> Expr {
+        let choice = if depth == 0 {
+            rng.below(6)
+        } else {
+            rng.below(9)
+        };
+        let operand = |rng: &mut Rng| Box::new(value(rng, 2));
+        match choice {
+            0 => Expr::Binary {
+                left: operand(rng),
+                op: rng.pick(&[
+                    BinaryOp::LT,
+                    BinaryOp::LEQ,
+                    BinaryOp::GT,
+                    BinaryOp::GEQ,
+                    BinaryOp::EQ,
+                    BinaryOp::NEQ,
+                ]),
+                right: operand(rng),
+            },
+            1 => Expr::IsNull {
+                expr: operand(rng),
+                negated: rng.below(2) == 0,
+                span: Span::default(),
+            },
+            2 => Expr::In {
+                expr: operand(rng),
+                list: vec![number(rng), Literal::String(String::from("y")), number(rng)],
+                span: Span::default(),
+            },
+            3 => Expr::Between {
+                expr: operand(rng),
+                low: number(rng),
+                high: number(rng),
+                span: Span::default(),
+            },
+            4 => Expr::Like {
+                expr: operand(rng),
+                pattern: String::from("%\"_"),
+                case_insensitive: rng.below(2) == 0,
+                span: Span::default(),
+            },
+            5 => Expr::Matches {
+                expr: operand(rng),
+                pattern: String::from("^a+$"),
+                pattern_span: Span::default(),
+                span: Span::default(),
+            },
+            6 => Expr::Not(Box::new(condition(rng, depth - 1))),
+            _ => Expr::Binary {
+                left: Box::new(condition(rng, depth - 1)),
+                op: rng.pick(&[BinaryOp::And, BinaryOp::Or]),
+                right: Box::new(condition(rng, depth - 1)),
+            },
+        }
+    }
+
+    #[test]
+    fn generated_trees_round_trip() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let items = (0..=rng.below(3))
+                .map(|_| SelectItem::Expr {
+                    expr: value(&mut rng, 3),
+                    alias: None,
+                })
+                .collect();
+            let select = Select {
+                items,
+                distinct: rng.below(2) == 0,
+                from: String::from("t"),
+                from_span: Span::default(),
+                filter: Some(condition(&mut rng, 3)),
+                group_by: vec![column(&mut rng)],
+                having: Some(condition(&mut rng, 2)),
+                order_by: vec![OrderItem {
+                    expr: value(&mut rng, 2),
+                    descending: rng.below(2) == 0,
+                }],
+                limit: Some(rng.below(10) as usize),
+                offset: rng.below(3) as usize,
+            };
+            assert_round_trip(&Statement::Select(Box::new(select)));
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+
+use crate::{
+    filter::{
+        BetweenFilter, CompareFilter, FilterRule, InFilter, InList, LikeFilter, LogicalFilter,
+        NotFilter, NullFilter, RegexFilter,
+    },
+    parser::{ast::Literal, Condition, ParsedQuery},
+    QueryError,
+};
+
+/// An analyzed query along with the filter rules the driver runs for it
+#[derive(Debug)]
+pub struct Plan {
+    pub query: ParsedQuery,
+    pub filter: Option<Box<dyn FilterRule>>,
+    pub having: Option<Box<dyn FilterRule>>,
+}
+
+/// Builds the execution objects for an analyzed query
+///
+/// The analyzer has already resolved every column and checked every type and pattern,
+/// so all that is left is to build the filter rules
+pub struct Planner;
+
+impl Planner {
+    pub fn plan(mut query: ParsedQuery) -> Result<Plan, QueryError> {
+        let filter = query.filter.take().map(Planner::filter).transpose()?;
+        let having = query.having.take().map(Planner::filter).transpose()?;
+        Ok(Plan {
+            query,
+            filter,
+            having,
+        })
+    }
+
+    fn filter(condition: Condition) -> Result<Box<dyn FilterRule>, QueryError> {
+        let rule: Box<dyn FilterRule> = match condition {
+            Condition::Logical { left, op, right } => Box::new(LogicalFilter {
+                f1: Planner::filter(*left)?,
+                op,
+                f2: Planner::filter(*right)?,
+            }),
+            Condition::Not(f) => Box::new(NotFilter {
+                f: Planner::filter(*f)?,
+            }),
+            Condition::Compare { left, op, right } => Box::new(CompareFilter { left, op, right }),
+            Condition::IsNull { expr, negated } => Box::new(NullFilter { expr, negated }),
+            Condition::In { expr, list } => {
+                let mut strings = HashSet::new();
+                let mut numbers = HashSet::new();
+                for val in list {
+                    match val {
+                        Literal::String(s) => strings.insert(s),
+                        num => numbers.insert(num.value()),
+                    };
+                }
+                let vals = if numbers.is_empty() {
+                    InList::Strings(strings)
+                } else {
+                    InList::Numbers(numbers)
+                };
+                Box::new(InFilter { expr, vals })
+            }
+            Condition::Between { expr, low, high } => Box::new(BetweenFilter {
+                expr,
+                low: low.value(),
+                high: high.value(),
+            }),
+            // Like patterns are compiled once here rather than per row, regexes already were by the analyzer
+            Condition::Like {
+                expr,
+                pattern,
+                case_insensitive,
+            } => Box::new(LikeFilter::new(expr, &pattern, case_insensitive)?),
+            Condition::Matches { expr, re } => Box::new(RegexFilter { expr, re }),
+        };
+        Ok(rule)
+    }
+}
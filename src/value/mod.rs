@@ -0,0 +1,372 @@
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
+
+use serde::Serialize;
+
+use crate::QueryError;
+
+/// A row of typed values, in the same column order as the dataset (or aggregated record) it came from
+pub type Row = Vec<Value>;
+
+/// A single typed cell, parsed once when the dataset is loaded
+///
+/// Ints and floats compare, hash and test equal as numbers, so `Int(65) == Float(65.0)`.
+/// Floats are always finite, so the ordering and equality are total
+#[derive(Debug, Clone, Serialize)]
+pub enum Value {
+    Null,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Date(Date),
+}
+
+impl Value {
+    /// Parses a csv field into the most specific type it fits, empty fields are NULL
+    pub fn parse(s: &str) -> Value {
+        if s.is_empty() {
+            Value::Null
+        } else if let Some(num) = Value::parse_number(s) {
+            num
+        } else if let Some(b) = Value::parse_bool(s) {
+            Value::Bool(b)
+        } else if let Some(date) = Date::parse(s) {
+            Value::Date(date)
+        } else {
+            Value::Str(s.to_string())
+        }
+    }
+
+    /// Parses integers, decimals and scientific notation like `-3`, `65.5` or `1.2e3`
+    ///
+    /// Infinities and NaN are not treated as numbers, so text like "nan" in a column stays text
+    pub fn parse_number(s: &str) -> Option<Value> {
+        if let Ok(i) = s.parse::<i64>() {
+            return Some(Value::Int(i));
+        }
+        match s.parse::<f64>() {
+            Ok(f) if f.is_finite() => Some(Value::Float(f)),
+            _ => None,
+        }
+    }
+
+    pub fn parse_bool(s: &str) -> Option<bool> {
+        match s {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Value::Int(_) | Value::Float(_))
+    }
+
+    /// The value if it is a number, None if it is NULL. For operations that only take numbers,
+    /// so anything else is an error
+    pub fn expect_number(&self) -> Result<Option<&Value>, QueryError> {
+        match self {
+            Value::Null => Ok(None),
+            val if val.is_number() => Ok(Some(val)),
+            _ => Err(QueryError::QueryFailed(
+                "Expected a number but got something else instead",
+            )),
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// Compares two values of the same kind, None if either is NULL or they can't be compared
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Float(b)) => Some(compare_int_float(*a, *b)),
+            (Value::Float(a), Value::Int(b)) => Some(compare_int_float(*b, *a).reverse()),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+            (Value::Date(a), Value::Date(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+
+    /// Total order used for sorting: values of the same kind compare naturally,
+    /// different kinds are ordered numbers, bools, dates then strings, and NULL sorts after everything
+    pub fn sort_cmp(&self, other: &Value) -> Ordering {
+        self.compare(other)
+            .unwrap_or_else(|| self.kind_rank().cmp(&other.kind_rank()))
+    }
+
+    fn kind_rank(&self) -> u8 {
+        match self {
+            Value::Int(_) | Value::Float(_) => 0,
+            Value::Bool(_) => 1,
+            Value::Date(_) => 2,
+            Value::Str(_) => 3,
+            Value::Null => 4,
+        }
+    }
+
+    /// Adds two numbers, widening to a float if either side is one or the integer sum overflows
+    pub fn add(&self, other: &Value) -> Option<Value> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(match a.checked_add(*b) {
+                Some(sum) => Value::Int(sum),
+                None => Value::Float(*a as f64 + *b as f64),
+            }),
+            _ => Some(Value::Float(self.as_f64()? + other.as_f64()?)),
+        }
+    }
+}
+
+/// 2^63, the first float past the end of i64's range. `i64::MAX as f64` rounds up to it
+const I64_END: f64 = 9_223_372_036_854_775_808.0;
+
+/// The int equal to the float, if there is one. Only integral floats in `[-2^63, 2^63)` have one
+fn exact_int(f: f64) -> Option<i64> {
+    if f.fract() == 0.0 && (-I64_END..I64_END).contains(&f) {
+        Some(f as i64)
+    } else {
+        None
+    }
+}
+
+/// Exact when the float is integral, so equality agrees with hashing even past 2^53
+fn compare_int_float(a: i64, b: f64) -> Ordering {
+    match exact_int(b) {
+        Some(b) => a.cmp(&b),
+        // Past the end of i64's range in either direction, so beyond every int
+        None if b.abs() >= I64_END => {
+            if b > 0.0 {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        }
+        None => (a as f64).partial_cmp(&b).unwrap_or(Ordering::Equal),
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            _ => self.compare(other) == Some(Ordering::Equal),
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Value::Null => 0.hash(state),
+            Value::Int(i) => i.hash(state),
+            // Integral floats have to hash like the equal int
+            Value::Float(f) => match exact_int(*f) {
+                Some(i) => i.hash(state),
+                None => f.to_bits().hash(state),
+            },
+            Value::Bool(b) => b.hash(state),
+            Value::Str(s) => s.hash(state),
+            Value::Date(d) => d.hash(state),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Date(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+/// A calendar date, written `YYYY-MM-DD`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Serialize)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    pub fn parse(s: &str) -> Option<Date> {
+        let bytes = s.as_bytes();
+        let well_formed = bytes.len() == 10
+            && bytes.iter().enumerate().all(|(i, b)| match i {
+                4 | 7 => *b == b'-',
+                _ => b.is_ascii_digit(),
+            });
+        if !well_formed {
+            return None;
+        }
+        let year = s[0..4].parse::<u16>().ok()?;
+        let month = s[5..7].parse::<u8>().ok()?;
+        let day = s[8..10].parse::<u8>().ok()?;
+        let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+        let days_in_month = match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if leap => 29,
+            2 => 28,
+            _ => return None,
+        };
+        if day == 0 || day > days_in_month {
+            return None;
+        }
+        Some(Date { year, month, day })
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// The type of a dataset column, inferred when the dataset is added to the catalog
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ColumnType {
+    Int,
+    Float,
+    Bool,
+    Date,
+    String,
+}
+
+impl ColumnType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Bool => "bool",
+            ColumnType::Date => "date",
+            ColumnType::String => "string",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ColumnType> {
+        match name {
+            "int" => Some(ColumnType::Int),
+            "float" => Some(ColumnType::Float),
+            "bool" => Some(ColumnType::Bool),
+            "date" => Some(ColumnType::Date),
+            "string" => Some(ColumnType::String),
+            _ => None,
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, ColumnType::Int | ColumnType::Float)
+    }
+
+    /// The type of a single value, None for NULL since it fits any type
+    pub fn of(val: &Value) -> Option<ColumnType> {
+        match val {
+            Value::Null => None,
+            Value::Int(_) => Some(ColumnType::Int),
+            Value::Float(_) => Some(ColumnType::Float),
+            Value::Bool(_) => Some(ColumnType::Bool),
+            Value::Date(_) => Some(ColumnType::Date),
+            Value::Str(_) => Some(ColumnType::String),
+        }
+    }
+
+    /// The narrowest type that can hold values of both types, anything can be held as a string
+    pub fn unify(self, other: ColumnType) -> ColumnType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnType::Int, ColumnType::Float) | (ColumnType::Float, ColumnType::Int) => {
+                ColumnType::Float
+            }
+            _ => ColumnType::String,
+        }
+    }
+
+    /// Parses a csv field as a value of this type, None if it doesn't fit. Empty fields are NULL
+    pub fn parse(&self, s: &str) -> Option<Value> {
+        if s.is_empty() {
+            return Some(Value::Null);
+        }
+        match self {
+            ColumnType::Int => s.parse::<i64>().ok().map(Value::Int),
+            ColumnType::Float => Value::parse_number(s)
+                .and_then(|num| num.as_f64())
+                .map(Value::Float),
+            ColumnType::Bool => Value::parse_bool(s).map(Value::Bool),
+            ColumnType::Date => Date::parse(s).map(Value::Date),
+            ColumnType::String => Some(Value::Str(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+
+    use super::*;
+
+    fn hash(val: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        val.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn ints_and_floats_are_equal_only_when_exact() {
+        let cases = [
+            (Value::Int(65), Value::Float(65.0), true),
+            (Value::Int(0), Value::Float(-0.0), true),
+            (Value::Int(i64::MIN), Value::Float(-I64_END), true),
+            (Value::Int(i64::MAX), Value::Float(I64_END), false),
+            (Value::Int(i64::MAX - 1), Value::Float(I64_END), false),
+            (
+                Value::Int(1 << 53),
+                Value::Float((1u64 << 53) as f64 + 2.0),
+                false,
+            ),
+            (Value::Int(3), Value::Float(3.5), false),
+        ];
+        for (int, float, equal) in cases {
+            assert_eq!(int == float, equal, "{:?} == {:?}", int, float);
+            if equal {
+                assert_eq!(hash(&int), hash(&float), "{:?} and {:?}", int, float);
+            }
+        }
+    }
+
+    #[test]
+    fn floats_past_the_end_of_i64_are_beyond_every_int() {
+        for float in [I64_END, f64::INFINITY, 1e300] {
+            assert_eq!(
+                Value::Int(i64::MAX).compare(&Value::Float(float)),
+                Some(Ordering::Less)
+            );
+            assert_eq!(
+                Value::Int(i64::MIN).compare(&Value::Float(-float * 2.0)),
+                Some(Ordering::Greater)
+            );
+        }
+    }
+}